@@ -7,11 +7,26 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also checks the images in `gfx` (see `validate_assets`) and slices the
+//! glyph sheet in `gfx/font` into a generated glyph table, see
+//! `generate_glyphs`.
 
 use std::env;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+const GLYPH_SHEET: &str = "gfx/font/digits.png";
+const GLYPH_MAP: &str = "gfx/font/digits.map";
+
+/// Width of the printer head, in dots
+const PRINTER_WIDTH: u32 = 384;
+/// Characters that the receipt layout code relies on
+const REQUIRED_GLYPHS: &str = "0123456789£x ,-#:/.";
+/// How far apart (in rows) the tops of two digits may be
+const DIGIT_HEIGHT_TOLERANCE: u32 = 1;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -29,8 +44,279 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
-    println!("cargo:rustc-link-arg-bins=--nmagic");
-    println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    validate_assets(Path::new("gfx"), Path::new(GLYPH_SHEET), Path::new(GLYPH_MAP));
+    generate_glyphs(Path::new(GLYPH_SHEET), Path::new(GLYPH_MAP), &out.join("glyphs.rs"));
+
+    // The linker scripts are only for the firmware, not tests on the host
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo:rustc-link-arg-bins=--nmagic");
+        println!("cargo:rustc-link-arg-bins=-Tlink.x");
+        println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
+}
+
+/// A greyscale image, one byte per pixel, as it would look printed on
+/// white paper
+struct Sheet {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Perceived brightness of a colour, with the Rec. 601 weights
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+impl Sheet {
+    fn load(path: &Path) -> Self {
+        let mut decoder = png::Decoder::new(
+            File::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)),
+        );
+        // Palettes, transparency and odd bit depths all come out as 8-bit
+        // grey or RGB, with or without alpha
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let pixels = buf
+            .chunks(info.color_type.samples())
+            .take((info.width * info.height) as usize)
+            .map(|px| {
+                let (luma, alpha) = match *px {
+                    [grey] => (grey, 255),
+                    [grey, alpha] => (grey, alpha),
+                    [r, g, b] => (luma(r, g, b), 255),
+                    [r, g, b, alpha] => (luma(r, g, b), alpha),
+                    _ => panic!("{}: unsupported colour type {:?}", path.display(), info.color_type),
+                };
+                // Transparent parts are paper
+                ((luma as u32 * alpha as u32 + 255 * (255 - alpha as u32)) / 255) as u8
+            })
+            .collect();
+        Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        }
+    }
+
+    fn is_ink(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize] < 128
+    }
+
+    /// First and last rows with any ink in the given column range
+    fn ink_rows(&self, x: u32, width: u32) -> Option<(u32, u32)> {
+        let has_ink = |y: u32| (x..x + width).any(|x| self.is_ink(x, y));
+        let top = (0..self.height).find(|&y| has_ink(y))?;
+        let bottom = (0..self.height).rev().find(|&y| has_ink(y))?;
+        Some((top, bottom))
+    }
+
+    /// Pack a column range into 1bpp rows, MSB first, as expected by `Image`
+    fn pack(&self, x: u32, width: u32) -> Vec<u8> {
+        let stride = width.div_ceil(8);
+        let mut data = vec![0u8; (stride * self.height) as usize];
+        for y in 0..self.height {
+            for dx in 0..width {
+                if self.is_ink(x + dx, y) {
+                    data[(y * stride + dx / 8) as usize] |= 0x80 >> (dx % 8);
+                }
+            }
+        }
+        data
+    }
+}
+
+struct GlyphEntry {
+    ch: char,
+    x: u32,
+    width: u32,
+    baseline: u32,
+}
+
+fn parse_map(path: &Path) -> Vec<GlyphEntry> {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut entries = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let bad_line = || -> ! {
+            panic!(
+                "{}:{}: expected `<char> <x> <width> <baseline>`",
+                path.display(),
+                lineno + 1
+            )
+        };
+        if fields.len() != 4 {
+            bad_line();
+        }
+        let ch = match fields[0] {
+            "space" => ' ',
+            "hash" => '#',
+            s if s.chars().count() == 1 => s.chars().next().unwrap(),
+            _ => bad_line(),
+        };
+        let num = |s: &str| s.parse::<u32>().unwrap_or_else(|_| bad_line());
+        entries.push(GlyphEntry {
+            ch,
+            x: num(fields[1]),
+            width: num(fields[2]),
+            baseline: num(fields[3]),
+        });
+    }
+    entries
+}
+
+/// Slice the glyph sheet according to the character map, and write out a
+/// `GLYPHS` table for `font.rs` to include.
+fn generate_glyphs(sheet_path: &Path, map_path: &Path, out_path: &Path) {
+    println!("cargo:rerun-if-changed={}", sheet_path.display());
+    println!("cargo:rerun-if-changed={}", map_path.display());
+
+    let sheet = Sheet::load(sheet_path);
+    let entries = parse_map(map_path);
+
+    let mut code = String::new();
+    writeln!(code, "pub static GLYPHS: [Glyph; {}] = [", entries.len()).unwrap();
+    for entry in &entries {
+        if entry.x + entry.width > sheet.width {
+            panic!(
+                "{}: glyph {:?} extends past the edge of {}",
+                map_path.display(),
+                entry.ch,
+                sheet_path.display()
+            );
+        }
+        let data = sheet.pack(entry.x, entry.width);
+        writeln!(
+            code,
+            "    Glyph {{ ch: {:?}, baseline: {}, image: Image {{ width: {}, height: {}, data: &{:?} }} }},",
+            entry.ch, entry.baseline, entry.width, sheet.height, data
+        )
+        .unwrap();
+    }
+    writeln!(code, "];").unwrap();
+
+    File::create(out_path)
+        .unwrap()
+        .write_all(code.as_bytes())
+        .unwrap();
+}
+
+/// Check that every image will fit on the paper, and that the glyph sheet
+/// has everything the layout code needs, failing the build if not.
+fn validate_assets(gfx_dir: &Path, sheet_path: &Path, map_path: &Path) {
+    println!("cargo:rerun-if-changed={}", gfx_dir.display());
+    let mut errors = Vec::new();
+
+    let mut images: Vec<PathBuf> = std::fs::read_dir(gfx_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .collect();
+    images.sort();
+
+    for path in &images {
+        let width = Sheet::load(path).width;
+        if width > PRINTER_WIDTH {
+            errors.push(format!(
+                "{}: image is {} px wide, the printer is only {} px wide",
+                path.display(),
+                width,
+                PRINTER_WIDTH
+            ));
+        }
+    }
+
+    let sheet = Sheet::load(sheet_path);
+    let entries = parse_map(map_path);
+
+    for c in REQUIRED_GLYPHS.chars() {
+        if !entries.iter().any(|entry| entry.ch == c) {
+            errors.push(format!("{}: no glyph for required character {:?}", map_path.display(), c));
+        }
+    }
+
+    let mut digit_tops = Vec::new();
+    for entry in &entries {
+        if entry.x + entry.width > sheet.width {
+            // Reported by generate_glyphs
+            continue;
+        }
+        if entry.width > PRINTER_WIDTH {
+            errors.push(format!(
+                "{}: glyph {:?} is {} px wide, the printer is only {} px wide",
+                map_path.display(),
+                entry.ch,
+                entry.width,
+                PRINTER_WIDTH
+            ));
+        }
+        if entry.baseline >= sheet.height {
+            errors.push(format!(
+                "{}: glyph {:?} has baseline {} but {} is only {} px tall",
+                map_path.display(),
+                entry.ch,
+                entry.baseline,
+                sheet_path.display(),
+                sheet.height
+            ));
+        }
+        if !entry.ch.is_ascii_digit() {
+            continue;
+        }
+        match sheet.ink_rows(entry.x, entry.width) {
+            Some((top, bottom)) => {
+                if bottom != entry.baseline {
+                    errors.push(format!(
+                        "{}: digit {:?} sits on row {} of {}, but its baseline is {}",
+                        map_path.display(),
+                        entry.ch,
+                        bottom,
+                        sheet_path.display(),
+                        entry.baseline
+                    ));
+                }
+                digit_tops.push((entry.ch, top));
+            }
+            None => errors.push(format!(
+                "{}: digit {:?} is blank in {}",
+                map_path.display(),
+                entry.ch,
+                sheet_path.display()
+            )),
+        }
+    }
+
+    if let (Some(highest), Some(lowest)) = (
+        digit_tops.iter().min_by_key(|(_, top)| *top),
+        digit_tops.iter().max_by_key(|(_, top)| *top),
+    ) {
+        if lowest.1 - highest.1 > DIGIT_HEIGHT_TOLERANCE {
+            errors.push(format!(
+                "{}: digits are different heights, {:?} starts on row {} but {:?} starts on row {}",
+                sheet_path.display(),
+                highest.0,
+                highest.1,
+                lowest.0,
+                lowest.1
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("error: {}", error);
+        }
+        panic!("{} problem(s) found in gfx assets", errors.len());
+    }
 }
@@ -0,0 +1,355 @@
+//! 1D barcodes, as bars for the framebuffer or as the printer's own
+//! `GS k` barcode command.
+//!
+//! Widths are in modules, the narrowest bar. At the printer's 203 dpi a
+//! module of 2 dots is 0.25 mm, about the smallest that scans reliably.
+
+use crate::framebuffer::Framebuffer;
+
+const MAX_MODULES: usize = 512;
+/// Light space either side of the bars, in modules
+const QUIET_ZONE: u8 = 10;
+
+/// Bar and space widths for each Code 128 symbol value
+const CODE128_PATTERNS: [&[u8; 6]; 106] = [
+    b"212222", b"222122", b"222221", b"121223", b"121322", b"131222", b"122213", b"122312",
+    b"132212", b"221213", b"221312", b"231212", b"112232", b"122132", b"122231", b"113222",
+    b"123122", b"123221", b"223211", b"221132", b"221231", b"213212", b"223112", b"312131",
+    b"311222", b"321122", b"321221", b"312212", b"322112", b"322211", b"212123", b"212321",
+    b"232121", b"111323", b"131123", b"131321", b"112313", b"132113", b"132311", b"211313",
+    b"231113", b"231311", b"112133", b"112331", b"132131", b"113123", b"113321", b"133121",
+    b"313121", b"211331", b"231131", b"213113", b"213311", b"213131", b"311123", b"311321",
+    b"331121", b"312113", b"312311", b"332111", b"314111", b"221411", b"431111", b"111224",
+    b"111422", b"121124", b"121421", b"141122", b"141221", b"112214", b"112412", b"122114",
+    b"122411", b"142112", b"142211", b"241211", b"221114", b"413111", b"241112", b"134111",
+    b"111242", b"121142", b"121241", b"114212", b"124112", b"124211", b"411212", b"421112",
+    b"421211", b"212141", b"214121", b"412121", b"111143", b"111341", b"131141", b"114113",
+    b"114311", b"411113", b"411311", b"113141", b"114131", b"311141", b"411131", b"211412",
+    b"211214", b"211232",
+];
+const CODE128_START_B: u8 = 104;
+const CODE128_START_C: u8 = 105;
+const CODE128_STOP: &[u8; 7] = b"2331112";
+
+/// EAN-13 left hand digits with odd parity, one bit per module
+const EAN_L: [u8; 10] = [
+    0b0001101, 0b0011001, 0b0010011, 0b0111101, 0b0100011, 0b0110001, 0b0101111, 0b0111011, 0b0110111, 0b0001011,
+];
+/// Which of the six left hand digits use even parity, set by the first digit
+const EAN_PARITY: [u8; 10] = [
+    0b000000, 0b001011, 0b001101, 0b001110, 0b010011, 0b011001, 0b011100, 0b010101, 0b010110, 0b011010,
+];
+
+/// Bars and spaces, including the quiet zones, one bit per module
+pub struct Barcode {
+    modules: [u8; MAX_MODULES / 8],
+    len: u16,
+    overflow: bool,
+}
+
+impl Barcode {
+    fn new() -> Self {
+        let mut barcode = Self { modules: [0; MAX_MODULES / 8], len: 0, overflow: false };
+        barcode.push(QUIET_ZONE, false);
+        barcode
+    }
+
+    fn push(&mut self, width: u8, dark: bool) {
+        for _ in 0..width {
+            if self.len as usize >= MAX_MODULES {
+                self.overflow = true;
+                return;
+            }
+            if dark {
+                self.modules[self.len as usize / 8] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    /// Alternating bars and spaces from a string of widths, starting dark
+    fn push_widths(&mut self, widths: &[u8]) {
+        for (i, width) in widths.iter().enumerate() {
+            self.push(width - b'0', i % 2 == 0);
+        }
+    }
+
+    /// Modules from the low `count` bits of `bits`, most significant first
+    fn push_bits(&mut self, bits: u8, count: u8) {
+        for i in (0..count).rev() {
+            self.push(1, bits >> i & 1 != 0);
+        }
+    }
+
+    fn finish(mut self) -> Option<Self> {
+        self.push(QUIET_ZONE, false);
+        (!self.overflow).then_some(self)
+    }
+
+    /// Width in modules, including the quiet zones
+    pub fn width(&self) -> u16 {
+        self.len
+    }
+
+    fn module(&self, i: u16) -> bool {
+        self.modules[i as usize / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    /// Draw the bars from (`x`, `y`), `module_width` dots per module and
+    /// `height` dots tall
+    pub fn draw<F: Framebuffer>(&self, fb: &mut F, x: u16, y: u16, module_width: u16, height: u16) {
+        let mut i = 0;
+        while i < self.len {
+            if !self.module(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < self.len && self.module(i) {
+                i += 1;
+            }
+            fb.fill_rect(x + start * module_width, y, (i - start) * module_width, height);
+        }
+    }
+}
+
+/// Encode printable ASCII as Code 128, using the denser code set C for
+/// strings of digits. Returns None for other characters or if it's too long.
+pub fn code128(text: &str) -> Option<Barcode> {
+    let bytes = text.as_bytes();
+    if bytes.iter().any(|b| !(b' '..=b'~').contains(b)) {
+        return None;
+    }
+    let numeric = bytes.len() >= 4 && bytes.len().is_multiple_of(2) && bytes.iter().all(u8::is_ascii_digit);
+
+    let mut barcode = Barcode::new();
+    let mut symbol = |value: u8, position: u32, checksum: &mut u32| {
+        barcode.push_widths(CODE128_PATTERNS[value as usize]);
+        *checksum += value as u32 * position.max(1);
+    };
+
+    let mut checksum = 0;
+    if numeric {
+        symbol(CODE128_START_C, 0, &mut checksum);
+        for (i, pair) in bytes.chunks(2).enumerate() {
+            symbol((pair[0] - b'0') * 10 + (pair[1] - b'0'), i as u32 + 1, &mut checksum);
+        }
+    } else {
+        symbol(CODE128_START_B, 0, &mut checksum);
+        for (i, byte) in bytes.iter().enumerate() {
+            symbol(byte - b' ', i as u32 + 1, &mut checksum);
+        }
+    }
+    barcode.push_widths(CODE128_PATTERNS[(checksum % 103) as usize]);
+    barcode.push_widths(CODE128_STOP);
+    barcode.finish()
+}
+
+/// Check digit for the first twelve digits of an EAN-13
+pub fn ean13_check_digit(digits: &[u8; 12]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| d as u32 * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// Parse twelve or thirteen digits, adding or checking the check digit
+pub fn ean13_digits(text: &str) -> Option<[u8; 13]> {
+    let bytes = text.as_bytes();
+    if !(bytes.len() == 12 || bytes.len() == 13) || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let mut digits = [0u8; 13];
+    for (digit, byte) in digits.iter_mut().zip(bytes) {
+        *digit = byte - b'0';
+    }
+    let check = ean13_check_digit(digits[..12].try_into().unwrap());
+    if bytes.len() == 13 && digits[12] != check {
+        return None;
+    }
+    digits[12] = check;
+    Some(digits)
+}
+
+pub fn ean13(text: &str) -> Option<Barcode> {
+    let digits = ean13_digits(text)?;
+    let parity = EAN_PARITY[digits[0] as usize];
+
+    let mut barcode = Barcode::new();
+    barcode.push_bits(0b101, 3);
+    for (i, &digit) in digits[1..7].iter().enumerate() {
+        let l = EAN_L[digit as usize];
+        if parity >> (5 - i) & 1 != 0 {
+            // Even parity is the right hand pattern mirrored
+            barcode.push_bits((!l & 0x7F).reverse_bits() >> 1, 7);
+        } else {
+            barcode.push_bits(l, 7);
+        }
+    }
+    barcode.push_bits(0b01010, 5);
+    for &digit in &digits[7..] {
+        barcode.push_bits(!EAN_L[digit as usize] & 0x7F, 7);
+    }
+    barcode.push_bits(0b101, 3);
+    barcode.finish()
+}
+
+/// `GS k` command for the printer to draw a Code 128 itself, written into
+/// `out`. Returns the length, or None if it doesn't fit.
+pub fn code128_command(text: &str, out: &mut [u8]) -> Option<usize> {
+    let len = text.len() + 2;
+    if len > u8::MAX as usize || out.len() < len + 4 {
+        return None;
+    }
+    out[..6].copy_from_slice(&[0x1D, 0x6B, 73, len as u8, b'{', b'B']);
+    out[6..len + 4].copy_from_slice(text.as_bytes());
+    Some(len + 4)
+}
+
+/// `GS k` command for an EAN-13, like `code128_command`. The printer adds
+/// the check digit itself.
+pub fn ean13_command(text: &str, out: &mut [u8]) -> Option<usize> {
+    let digits = ean13_digits(text)?;
+    if out.len() < 16 {
+        return None;
+    }
+    out[..4].copy_from_slice(&[0x1D, 0x6B, 67, 12]);
+    for (byte, digit) in out[4..16].iter_mut().zip(&digits[..12]) {
+        *byte = b'0' + digit;
+    }
+    Some(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Widths of the bars and spaces between the quiet zones
+    fn widths(barcode: &Barcode) -> Vec<u8> {
+        let mut widths: Vec<u8> = Vec::new();
+        let mut last = None;
+        for i in QUIET_ZONE as u16..barcode.width() - QUIET_ZONE as u16 {
+            let dark = barcode.module(i);
+            match widths.last_mut() {
+                Some(width) if last == Some(dark) => *width += 1,
+                _ => widths.push(1),
+            }
+            last = Some(dark);
+        }
+        widths
+    }
+
+    /// The symbol values in a Code 128, then the stop pattern's widths
+    fn code128_symbols(barcode: &Barcode) -> (Vec<u8>, Vec<u8>) {
+        let widths = widths(barcode);
+        let (symbols, stop) = widths.split_at(widths.len() - 7);
+        let symbols = symbols
+            .chunks(6)
+            .map(|chunk| {
+                let pattern: Vec<u8> = chunk.iter().map(|w| w + b'0').collect();
+                CODE128_PATTERNS.iter().position(|p| p[..] == pattern[..]).unwrap() as u8
+            })
+            .collect();
+        (symbols, stop.iter().map(|w| w + b'0').collect())
+    }
+
+    #[test]
+    fn code128_set_b_with_checksum() {
+        let (symbols, stop) = code128_symbols(&code128("PJJ123C").unwrap());
+        // 104 + 48×1 + 42×2 + 42×3 + 17×4 + 18×5 + 19×6 + 35×7 = 879,
+        // which is 55 mod 103
+        assert_eq!(symbols, [104, 48, 42, 42, 17, 18, 19, 35, 55]);
+        assert_eq!(stop, CODE128_STOP);
+    }
+
+    #[test]
+    fn code128_even_digit_runs_use_set_c() {
+        let (symbols, _) = code128_symbols(&code128("12345678").unwrap());
+        // 105 + 12×1 + 34×2 + 56×3 + 78×4 = 665, which is 47 mod 103
+        assert_eq!(symbols, [105, 12, 34, 56, 78, 47]);
+
+        // An odd number of digits, or too few to be worth it, stay in set B
+        let (symbols, _) = code128_symbols(&code128("12345").unwrap());
+        assert_eq!(symbols[0], CODE128_START_B);
+        assert_eq!(symbols.len(), 1 + 5 + 1);
+        let (symbols, _) = code128_symbols(&code128("12").unwrap());
+        assert_eq!(symbols, [104, 17, 18, (104 + 17 + 18 * 2) % 103]);
+    }
+
+    #[test]
+    fn code128_refuses_what_it_cant_encode() {
+        assert!(code128("café").is_none());
+        assert!(code128("tab\there").is_none());
+        // Each character is 11 modules, so this can't fit
+        assert!(code128(&"A".repeat(50)).is_none());
+    }
+
+    #[test]
+    fn ean13_check_digits() {
+        assert_eq!(ean13_check_digit(&[4, 0, 0, 6, 3, 8, 1, 3, 3, 3, 9, 3]), 1);
+        assert_eq!(ean13_check_digit(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]), 5);
+        assert_eq!(ean13_digits("400638133393"), Some([4, 0, 0, 6, 3, 8, 1, 3, 3, 3, 9, 3, 1]));
+        assert_eq!(ean13_digits("4006381333931"), Some([4, 0, 0, 6, 3, 8, 1, 3, 3, 3, 9, 3, 1]));
+        assert_eq!(ean13_digits("4006381333932"), None);
+        assert_eq!(ean13_digits("40063813339"), None);
+        assert_eq!(ean13_digits("40063813339x"), None);
+    }
+
+    #[test]
+    fn ean13_parity_follows_the_first_digit() {
+        const L: [u8; 10] = [
+            0b0001101, 0b0011001, 0b0010011, 0b0111101, 0b0100011, 0b0110001, 0b0101111, 0b0111011, 0b0110111,
+            0b0001011,
+        ];
+        const G: [u8; 10] = [
+            0b0100111, 0b0110011, 0b0011011, 0b0100001, 0b0011101, 0b0111001, 0b0000101, 0b0010001, 0b0001001,
+            0b0010111,
+        ];
+        const PARITY: [&str; 10] =
+            ["LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL", "LGGLGL"];
+
+        for first in 0..10u8 {
+            let text = format!("{}12345678901", first);
+            let barcode = ean13(&text).unwrap();
+            assert_eq!(barcode.width(), 95 + 2 * QUIET_ZONE as u16);
+            let digits = ean13_digits(&text).unwrap();
+            let mut parity = String::new();
+            for (i, &digit) in digits[1..7].iter().enumerate() {
+                let start = QUIET_ZONE as u16 + 3 + i as u16 * 7;
+                let bits = (0..7).fold(0u8, |bits, m| bits << 1 | barcode.module(start + m) as u8);
+                match bits {
+                    _ if bits == L[digit as usize] => parity.push('L'),
+                    _ if bits == G[digit as usize] => parity.push('G'),
+                    _ => panic!("digit {} isn't L or G: {:07b}", digit, bits),
+                }
+            }
+            assert_eq!(parity, PARITY[first as usize], "first digit {}", first);
+        }
+    }
+
+    #[test]
+    fn code128_printer_command() {
+        let mut out = [0u8; 32];
+        assert_eq!(code128_command("1234", &mut out), Some(10));
+        assert_eq!(out[..10], [0x1D, 0x6B, 73, 6, b'{', b'B', b'1', b'2', b'3', b'4']);
+
+        // The length byte counts the code set selector too
+        assert_eq!(code128_command("", &mut out), Some(6));
+        assert_eq!(out[3], 2);
+        assert_eq!(code128_command("12345", &mut [0u8; 10]), None);
+        assert_eq!(code128_command(&"1".repeat(254), &mut [0u8; 300]), None);
+        assert_eq!(code128_command(&"1".repeat(253), &mut [0u8; 300]), Some(259));
+    }
+
+    #[test]
+    fn ean13_printer_command() {
+        let mut out = [0u8; 16];
+        assert_eq!(ean13_command("4006381333931", &mut out), Some(16));
+        assert_eq!(out[..4], [0x1D, 0x6B, 67, 12]);
+        assert_eq!(&out[4..], b"400638133393");
+        assert_eq!(ean13_command("4006381333932", &mut out), None);
+        assert_eq!(ean13_command("400638133393", &mut [0u8; 15]), None);
+    }
+}
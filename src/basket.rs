@@ -0,0 +1,55 @@
+//! What's in the basket, and the key presses and scans that fill it.
+
+use heapless::Vec;
+
+use crate::catalogue;
+use crate::images::Images;
+
+/// Most items that can be rung up in one transaction
+pub const MAX_LINES: usize = 32;
+
+/// Identical items rung up in the same transaction
+#[derive(Clone, Copy)]
+pub struct ReceiptGroup {
+    pub image: Images,
+    pub quantity: u8,
+    pub unit_price: u16,
+}
+
+impl ReceiptGroup {
+    pub fn price(&self) -> u16 {
+        self.unit_price * self.quantity as u16
+    }
+}
+
+/// Combine identical items, and sort them into catalogue order
+pub fn group_lines(lines: &[LineItem]) -> Vec<ReceiptGroup, MAX_LINES> {
+    let mut groups: Vec<ReceiptGroup, MAX_LINES> = Vec::new();
+    for line in lines {
+        match groups.iter_mut().find(|g| g.image == line.image && g.unit_price == line.price) {
+            Some(group) => group.quantity += 1,
+            None => {
+                // There can't be more groups than lines
+                let _ = groups.push(ReceiptGroup { image: line.image, quantity: 1, unit_price: line.price });
+            }
+        }
+    }
+    groups.sort_unstable_by_key(|g| (catalogue::product_id(g.image), g.unit_price));
+    groups
+}
+
+#[derive(Clone, Copy)]
+pub struct LineItem {
+    pub image: Images,
+    pub price: u16,
+}
+
+pub enum InputEvent {
+    ProduceButtonPressed{ image: Images, price: u16 },
+    VoidButtonPressed,
+    VoidButtonLongPressed,
+    TotalButtonPressed,
+    TotalButtonLongPressed,
+    /// The scanner read a code that isn't in the catalogue
+    UnknownBarcode,
+}
@@ -0,0 +1,53 @@
+use crate::color::Color;
+use crate::images::Images;
+use crate::tax::TaxClass;
+
+pub struct Product {
+    pub image: Images,
+    pub price: u16,
+    pub tax: TaxClass,
+    /// EAN-13 for shelf labels, in the in-store range starting with 2
+    pub barcode: &'static str,
+    /// The LEDs flash this when it's rung up
+    pub color: Color,
+}
+
+/// Products, in the order of the keys they're assigned to
+pub static PRODUCTS: [Product; 8] = [
+    Product { image: Images::Banana, price: 2, tax: TaxClass::Zero, barcode: "2000000000015", color: Color::rgb(137, 120, 0) },
+    Product { image: Images::Juice, price: 1, tax: TaxClass::Standard, barcode: "2000000000022", color: Color::rgb(137, 79, 0) },
+    Product { image: Images::Eggs, price: 3, tax: TaxClass::Zero, barcode: "2000000000039", color: Color::new(0, 0, 0, 120) },
+    Product { image: Images::Cheese, price: 4, tax: TaxClass::Zero, barcode: "2000000000046", color: Color::new(120, 110, 0, 48) },
+    Product { image: Images::Bread, price: 5, tax: TaxClass::Zero, barcode: "2000000000053", color: Color::rgb(110, 70, 18) },
+    Product { image: Images::Sberry, price: 6, tax: TaxClass::Zero, barcode: "2000000000060", color: Color::rgb(137, 0, 32) },
+    Product { image: Images::Chicken, price: 7, tax: TaxClass::Zero, barcode: "2000000000077", color: Color::new(120, 86, 48, 32) },
+    Product { image: Images::Pie, price: 8, tax: TaxClass::Standard, barcode: "2000000000084", color: Color::rgb(99, 48, 0) },
+];
+
+/// Stable identifier for a product, used when storing items in flash
+pub fn product_id(image: Images) -> Option<u8> {
+    PRODUCTS.iter().position(|p| p.image == image).map(|i| i as u8)
+}
+
+pub fn product(id: u8) -> Option<&'static Product> {
+    PRODUCTS.get(id as usize)
+}
+
+/// Tax class of a product, treating anything unknown as standard rated
+pub fn tax_class(image: Images) -> TaxClass {
+    PRODUCTS
+        .iter()
+        .find(|p| p.image == image)
+        .map_or(TaxClass::Standard, |p| p.tax)
+}
+
+/// Find a product from a scanned EAN-13 or UPC-A
+pub fn by_barcode(code: &str) -> Option<&'static Product> {
+    PRODUCTS.iter().find(|p| barcode_matches(p.barcode, code))
+}
+
+/// Whether a scanned code is the EAN-13 `barcode`, or the UPC-A for it,
+/// which is the same code without the leading zero
+pub fn barcode_matches(barcode: &str, code: &str) -> bool {
+    barcode == code || (code.len() == 12 && barcode.strip_prefix('0') == Some(code))
+}
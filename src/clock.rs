@@ -0,0 +1,178 @@
+//! Wall clock time from the RP2040 RTC.
+//!
+//! The RTC loses its time whenever the till is unplugged, so `now` returns
+//! `None` until the time has been set from the keypad or over USB.
+
+use core::cell::RefCell;
+
+use defmt::Format;
+use embassy_rp::peripherals::RTC;
+use embassy_rp::rtc::{DateTime, DayOfWeek, Rtc, RtcError};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<Option<Rtc<'static, RTC>>>> =
+    Mutex::new(RefCell::new(None));
+
+pub fn init(rtc: Rtc<'static, RTC>) {
+    CLOCK.lock(|clock| *clock.borrow_mut() = Some(rtc));
+}
+
+pub fn now() -> Option<Timestamp> {
+    CLOCK.lock(|clock| {
+        let clock = clock.borrow();
+        let rtc = clock.as_ref()?;
+        let now = rtc.now().ok()?;
+        Some(Timestamp {
+            year: now.year,
+            month: now.month,
+            day: now.day,
+            hour: now.hour,
+            minute: now.minute,
+            second: now.second,
+        })
+    })
+}
+
+pub fn set(time: &Timestamp) -> Result<(), RtcError> {
+    let datetime = DateTime {
+        year: time.year,
+        month: time.month,
+        day: time.day,
+        day_of_week: time.day_of_week(),
+        hour: time.hour,
+        minute: time.minute,
+        second: time.second,
+    };
+    CLOCK.lock(|clock| match clock.borrow_mut().as_mut() {
+        Some(rtc) => rtc.set_datetime(datetime),
+        None => Err(RtcError::NotRunning),
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// Where the keypad editor starts if the clock has never been set
+    pub const DEFAULT: Timestamp = Timestamp {
+        year: 2025,
+        month: 1,
+        day: 1,
+        hour: 12,
+        minute: 0,
+        second: 0,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        (2000..=2099).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    fn day_of_week(&self) -> DayOfWeek {
+        // Sakamoto's method
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = if self.month < 3 { self.year - 1 } else { self.year };
+        let day = (year + year / 4 - year / 100 + year / 400
+            + OFFSETS[self.month as usize - 1]
+            + self.day as u16)
+            % 7;
+        match day {
+            0 => DayOfWeek::Sunday,
+            1 => DayOfWeek::Monday,
+            2 => DayOfWeek::Tuesday,
+            3 => DayOfWeek::Wednesday,
+            4 => DayOfWeek::Thursday,
+            5 => DayOfWeek::Friday,
+            _ => DayOfWeek::Saturday,
+        }
+    }
+
+    /// Parse `YYYY-MM-DD HH:MM` with optional `:SS`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (date, time) = text.trim().split_once(' ')?;
+        let mut date = date.split('-');
+        let mut time = time.trim().split(':');
+        let parsed = Self {
+            year: date.next()?.parse().ok()?,
+            month: date.next()?.parse().ok()?,
+            day: date.next()?.parse().ok()?,
+            hour: time.next()?.parse().ok()?,
+            minute: time.next()?.parse().ok()?,
+            second: match time.next() {
+                Some(second) => second.parse().ok()?,
+                None => 0,
+            },
+        };
+        if date.next().is_some() || time.next().is_some() || !parsed.is_valid() {
+            return None;
+        }
+        Some(parsed)
+    }
+
+    /// Step one field up or down, wrapping within its range
+    pub fn adjust(&mut self, field: TimeField, up: bool) {
+        fn step(value: u16, min: u16, max: u16, up: bool) -> u16 {
+            match (up, value) {
+                (true, v) if v >= max => min,
+                (true, v) => v + 1,
+                (false, v) if v <= min => max,
+                (false, v) => v - 1,
+            }
+        }
+        match field {
+            TimeField::Year => self.year = step(self.year, 2000, 2099, up),
+            TimeField::Month => self.month = step(self.month as u16, 1, 12, up) as u8,
+            TimeField::Day => {
+                let max = days_in_month(self.year, self.month) as u16;
+                self.day = step(self.day as u16, 1, max, up) as u8
+            }
+            TimeField::Hour => self.hour = step(self.hour as u16, 0, 23, up) as u8,
+            TimeField::Minute => self.minute = step(self.minute as u16, 0, 59, up) as u8,
+        }
+        self.day = self.day.min(days_in_month(self.year, self.month));
+        self.second = 0;
+    }
+}
+
+/// The fields of a `Timestamp`, in the order the keypad editor visits them
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum TimeField {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+}
+
+impl TimeField {
+    pub fn next(self) -> Option<Self> {
+        match self {
+            TimeField::Year => Some(TimeField::Month),
+            TimeField::Month => Some(TimeField::Day),
+            TimeField::Day => Some(TimeField::Hour),
+            TimeField::Hour => Some(TimeField::Minute),
+            TimeField::Minute => None,
+        }
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
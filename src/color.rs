@@ -0,0 +1,343 @@
+//! Colours for the LEDs, and the corrections applied on the way out to
+//! the strip.
+//!
+//! Colours are written the way they look, in sRGB, so a fade from 0 to 255
+//! looks even. The strip is linear, so `gamma` is applied last of all,
+//! along with the brightness and current limits.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::Format;
+
+/// Full brightness drawn by one channel of an SK6812, in microamps
+const CHANNEL_UA: u32 = 18_000;
+/// Drawn by each pixel's driver chip even when it's dark
+const IDLE_UA: u32 = 1_000;
+
+/// sRGB to linear, scaled to 0 to 255
+static GAMMA: [u8; 256] = [
+      0,   0,   0,   0,   0,   0,   0,   1,   1,   1,   1,   1,   1,   1,   1,   1,
+      1,   1,   2,   2,   2,   2,   2,   2,   2,   2,   3,   3,   3,   3,   3,   3,
+      4,   4,   4,   4,   4,   5,   5,   5,   5,   6,   6,   6,   6,   7,   7,   7,
+      8,   8,   8,   8,   9,   9,   9,  10,  10,  10,  11,  11,  12,  12,  12,  13,
+     13,  13,  14,  14,  15,  15,  16,  16,  17,  17,  17,  18,  18,  19,  19,  20,
+     20,  21,  22,  22,  23,  23,  24,  24,  25,  25,  26,  27,  27,  28,  29,  29,
+     30,  30,  31,  32,  32,  33,  34,  35,  35,  36,  37,  37,  38,  39,  40,  41,
+     41,  42,  43,  44,  45,  45,  46,  47,  48,  49,  50,  51,  51,  52,  53,  54,
+     55,  56,  57,  58,  59,  60,  61,  62,  63,  64,  65,  66,  67,  68,  69,  70,
+     71,  72,  73,  74,  76,  77,  78,  79,  80,  81,  82,  84,  85,  86,  87,  88,
+     90,  91,  92,  93,  95,  96,  97,  99, 100, 101, 103, 104, 105, 107, 108, 109,
+    111, 112, 114, 115, 116, 118, 119, 121, 122, 124, 125, 127, 128, 130, 131, 133,
+    134, 136, 138, 139, 141, 142, 144, 146, 147, 149, 151, 152, 154, 156, 157, 159,
+    161, 163, 164, 166, 168, 170, 171, 173, 175, 177, 179, 181, 183, 184, 186, 188,
+    190, 192, 194, 196, 198, 200, 202, 204, 206, 208, 210, 212, 214, 216, 218, 220,
+    222, 224, 226, 229, 231, 233, 235, 237, 239, 242, 244, 246, 248, 250, 253, 255,
+];
+
+static BRIGHTNESS: AtomicU8 = AtomicU8::new(255);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Format)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+/// Hue in degrees, saturation and value out of 255
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub struct Hsv {
+    pub h: u16,
+    pub s: u8,
+    pub v: u8,
+}
+
+/// Hue in degrees, saturation and lightness out of 255
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub struct Hsl {
+    pub h: u16,
+    pub s: u8,
+    pub l: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8, w: u8) -> Self {
+        Self { r, g, b, w }
+    }
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r, g, b, 0)
+    }
+
+    pub fn from_hsv(hsv: Hsv) -> Self {
+        let s = hsv.s as u32;
+        let v = hsv.v as u32;
+        let (sector, f) = hue_sector(hsv.h);
+        let p = v * (255 - s) / 255;
+        let q = v * (255 * 255 - s * f) / (255 * 255);
+        let t = v * (255 * 255 - s * (255 - f)) / (255 * 255);
+        let (r, g, b) = match sector {
+            0 => (v, t, p),
+            1 => (q, v, p),
+            2 => (p, v, t),
+            3 => (p, q, v),
+            4 => (t, p, v),
+            _ => (v, p, q),
+        };
+        Self::rgb(r as u8, g as u8, b as u8)
+    }
+
+    pub fn from_hsl(hsl: Hsl) -> Self {
+        let l = hsl.l as u32;
+        // Chroma, the spread between the brightest and dimmest channel
+        let c = (255 - (2 * l).abs_diff(255)) * hsl.s as u32 / 255;
+        let min = l - c / 2;
+        let (sector, f) = hue_sector(hsl.h);
+        let rising = min + c * f / 255;
+        let falling = min + c * (255 - f) / 255;
+        let max = min + c;
+        let (r, g, b) = match sector {
+            0 => (max, rising, min),
+            1 => (falling, max, min),
+            2 => (min, max, rising),
+            3 => (min, falling, max),
+            4 => (rising, min, max),
+            _ => (max, min, falling),
+        };
+        Self::rgb(r as u8, g as u8, b as u8)
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let s = if max == 0 { 0 } else { div_round((max - min) as u32 * 255, max as u32) as u8 };
+        Hsv { h: self.hue(), s, v: max }
+    }
+
+    pub fn to_hsl(self) -> Hsl {
+        let max = self.r.max(self.g).max(self.b) as u32;
+        let min = self.r.min(self.g).min(self.b) as u32;
+        let sum = max + min;
+        let spread = 255 - sum.abs_diff(255);
+        let s = if spread == 0 { 0 } else { div_round((max - min) * 255, spread) as u8 };
+        Hsl { h: self.hue(), s, l: div_round(sum, 2) as u8 }
+    }
+
+    /// Hue in degrees, 0 for greys
+    fn hue(&self) -> u16 {
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0 {
+            return 0;
+        }
+        let (base, diff) = if max == r {
+            (0, g - b)
+        } else if max == g {
+            (120, b - r)
+        } else {
+            (240, r - g)
+        };
+        // Rounded to the nearest degree, either side of zero
+        let offset = (diff * 120 + delta * diff.signum()) / (delta * 2);
+        (base + offset).rem_euclid(360) as u16
+    }
+
+    /// Move the white shared by red, green and blue onto the white channel,
+    /// which is brighter and draws less for the same light
+    pub fn extract_white(self) -> Self {
+        let white = self.r.min(self.g).min(self.b);
+        Self::new(self.r - white, self.g - white, self.b - white, self.w.saturating_add(white))
+    }
+
+    /// Mix towards `other` by `fraction` out of 256
+    pub fn lerp(self, other: Color, fraction: u32) -> Self {
+        let fraction = fraction.min(256);
+        let mix = |a: u8, b: u8| ((a as u32 * (256 - fraction) + b as u32 * fraction) / 256) as u8;
+        Self::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b), mix(self.w, other.w))
+    }
+
+    /// Dim by `level` out of 256
+    pub fn scale(self, level: u32) -> Self {
+        Color::BLACK.lerp(self, level)
+    }
+
+    /// Convert each channel from sRGB to the linear drive the LED needs
+    pub fn gamma(self) -> Self {
+        let g = |c: u8| GAMMA[c as usize];
+        Self::new(g(self.r), g(self.g), g(self.b), g(self.w))
+    }
+}
+
+/// Which sixth of the colour wheel a hue is in, and how far through it
+/// out of 255
+fn hue_sector(h: u16) -> (u16, u32) {
+    let h = h % 360;
+    (h / 60, (h % 60) as u32 * 255 / 60)
+}
+
+fn div_round(a: u32, b: u32) -> u32 {
+    (a + b / 2) / b
+}
+
+/// Scale every colour sent to the strip, out of 255
+pub fn set_brightness(level: u8) {
+    BRIGHTNESS.store(level, Ordering::Relaxed);
+}
+
+pub fn brightness() -> u8 {
+    BRIGHTNESS.load(Ordering::Relaxed)
+}
+
+/// Roughly how much a frame of linear, already corrected, colours draws
+pub fn current_ma(frame: &[Color]) -> u32 {
+    let channels: u32 = frame
+        .iter()
+        .map(|c| c.r as u32 + c.g as u32 + c.b as u32 + c.w as u32)
+        .sum();
+    (channels * CHANNEL_UA / 255 + frame.len() as u32 * IDLE_UA) / 1000
+}
+
+/// Dim a frame evenly so it draws no more than `limit_ma`, or as little
+/// as it can if that's less than the strip draws when dark
+pub fn limit_current(frame: &mut [Color], limit_ma: u32) {
+    let idle_ma = frame.len() as u32 * IDLE_UA / 1000;
+    if limit_ma <= idle_ma {
+        frame.fill(Color::BLACK);
+        return;
+    }
+    let drawn = current_ma(frame);
+    if drawn <= limit_ma {
+        return;
+    }
+    let level = (limit_ma - idle_ma) * 256 / (drawn - idle_ma);
+    for color in frame {
+        *color = color.scale(level);
+    }
+}
+
+/// Everything between a pattern's colours and the strip: gamma, the
+/// global brightness, white extraction and the current limit
+pub fn correct(frame: &mut [Color], extract_white: bool, limit_ma: u32) {
+    let level = brightness() as u32 + 1;
+    for color in frame.iter_mut() {
+        let mut corrected = color.gamma().scale(level);
+        if extract_white {
+            corrected = corrected.extract_white();
+        }
+        *color = corrected;
+    }
+    limit_current(frame, limit_ma);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARIES: [(Color, u16); 6] = [
+        (Color::rgb(255, 0, 0), 0),
+        (Color::rgb(255, 255, 0), 60),
+        (Color::rgb(0, 255, 0), 120),
+        (Color::rgb(0, 255, 255), 180),
+        (Color::rgb(0, 0, 255), 240),
+        (Color::rgb(255, 0, 255), 300),
+    ];
+    const GREYS: [u8; 4] = [0, 1, 128, 255];
+
+    /// Each channel within one step, as the conversions round
+    fn assert_close(a: Color, b: Color) {
+        let channels = |c: Color| [c.r, c.g, c.b, c.w];
+        for (x, y) in channels(a).into_iter().zip(channels(b)) {
+            assert!(x.abs_diff(y) <= 1, "{a:?} vs {b:?}");
+        }
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for (color, hue) in PRIMARIES {
+            let hsv = color.to_hsv();
+            assert_eq!((hsv.h, hsv.s, hsv.v), (hue, 255, 255));
+            assert_eq!(Color::from_hsv(hsv), color);
+        }
+        for grey in GREYS {
+            let color = Color::rgb(grey, grey, grey);
+            let hsv = color.to_hsv();
+            assert_eq!((hsv.h, hsv.s, hsv.v), (0, 0, grey));
+            assert_eq!(Color::from_hsv(hsv), color);
+        }
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for (color, hue) in PRIMARIES {
+            let hsl = color.to_hsl();
+            assert_eq!((hsl.h, hsl.s, hsl.l), (hue, 255, 128));
+            assert_close(Color::from_hsl(hsl), color);
+        }
+        for grey in GREYS {
+            let color = Color::rgb(grey, grey, grey);
+            let hsl = color.to_hsl();
+            assert_eq!((hsl.h, hsl.s, hsl.l), (0, 0, grey));
+            assert_eq!(Color::from_hsl(hsl), color);
+        }
+    }
+
+    #[test]
+    fn gamma_endpoints() {
+        assert_eq!(Color::BLACK.gamma(), Color::BLACK);
+        assert_eq!(Color::new(255, 255, 255, 255).gamma(), Color::new(255, 255, 255, 255));
+        assert!(GAMMA.windows(2).all(|w| w[0] <= w[1]));
+        // Mid grey in sRGB is about a fifth of full drive
+        assert_eq!(Color::rgb(128, 128, 128).gamma(), Color::rgb(55, 55, 55));
+    }
+
+    #[test]
+    fn extract_white() {
+        assert_eq!(Color::rgb(200, 100, 50).extract_white(), Color::new(150, 50, 0, 50));
+        assert_eq!(Color::rgb(255, 0, 0).extract_white(), Color::rgb(255, 0, 0));
+        assert_eq!(Color::rgb(80, 80, 80).extract_white(), Color::new(0, 0, 0, 80));
+        // White already there is added to, up to full
+        assert_eq!(Color::new(10, 20, 30, 250).extract_white(), Color::new(0, 10, 20, 255));
+    }
+
+    #[test]
+    fn current_of_a_frame() {
+        let mut frame = [Color::BLACK; 9];
+        assert_eq!(current_ma(&frame), 9);
+        frame[0] = Color::new(255, 255, 255, 255);
+        assert_eq!(current_ma(&frame), 4 * 18 + 9);
+        frame[1] = Color::rgb(255, 0, 0);
+        assert_eq!(current_ma(&frame), 5 * 18 + 9);
+    }
+
+    #[test]
+    fn limit_leaves_a_frame_under_it_alone() {
+        let mut frame = [Color::rgb(255, 0, 0); 9];
+        limit_current(&mut frame, 300);
+        assert_eq!(frame, [Color::rgb(255, 0, 0); 9]);
+    }
+
+    #[test]
+    fn limit_dims_a_frame_over_it() {
+        let mut frame = [Color::new(255, 255, 255, 255); 9];
+        limit_current(&mut frame, 300);
+        let drawn = current_ma(&frame);
+        assert!((290..=300).contains(&drawn), "{drawn} mA");
+        // Evenly, so every pixel is the same
+        assert!(frame.iter().all(|&c| c == frame[0]));
+    }
+
+    #[test]
+    fn limit_below_idle_goes_black() {
+        // Even a dark strip draws 9 mA, so there's nothing to divide up
+        let mut frame = [Color::BLACK; 9];
+        limit_current(&mut frame, 5);
+        assert_eq!(frame, [Color::BLACK; 9]);
+
+        let mut frame = [Color::rgb(255, 0, 0); 9];
+        limit_current(&mut frame, 9);
+        assert_eq!(frame, [Color::BLACK; 9]);
+    }
+}
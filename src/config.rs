@@ -0,0 +1,98 @@
+//! Settings that change how the till behaves
+
+use embassy_time::Duration;
+
+use crate::images::Images;
+use crate::promotions::Promotion;
+use crate::qr::EcLevel;
+use crate::sk6812::Grbw;
+use crate::tax::TaxClass;
+
+/// What to do at boot if the till lost power part way through a basket
+pub enum Recovery {
+    /// Carry on adding to the interrupted basket
+    Resume,
+    /// Print a void slip and start afresh
+    Void,
+}
+
+pub const RECOVERY: Recovery = Recovery::Resume;
+
+/// When receipt lines are sent to the printer
+pub enum PrintMode {
+    /// Print each item as soon as it's rung up
+    Streaming,
+    /// Hold the basket and print it all, grouped by product, at total
+    Buffered,
+}
+
+pub const PRINT_MODE: PrintMode = PrintMode::Streaming;
+
+/// What to do with a basket that has been left alone for `IDLE_TIMEOUT`
+pub enum IdleAction {
+    Void,
+    Total,
+}
+
+pub const IDLE_ACTION: IdleAction = IdleAction::Void;
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+/// The LED pulses for this long before the idle timeout fires
+pub const IDLE_WARNING: Duration = Duration::from_secs(20);
+
+/// VAT rate for each tax class, in basis points (2000 is 20%)
+pub const TAX_RATES: [(TaxClass, u32); 3] = [
+    (TaxClass::Standard, 2000),
+    (TaxClass::Reduced, 500),
+    (TaxClass::Zero, 0),
+];
+/// Print net and VAT amounts above the total
+pub const SHOW_VAT: bool = true;
+
+/// Offers applied at total, in order of priority
+pub const PROMOTIONS: &[Promotion] = &[
+    Promotion::MultiBuy { product: Images::Banana, quantity: 3, price: 5 },
+    Promotion::BuyOneGetOneFree { product: Images::Pie },
+    Promotion::MealDeal { products: &[Images::Bread, Images::Cheese, Images::Juice], price: 8 },
+];
+
+/// Every this many customers gets a coupon after their receipt
+pub const LOYALTY_EVERY: u32 = 10;
+/// Print a card after each receipt showing progress to the next coupon
+pub const STAMP_CARD: bool = true;
+
+/// Print a QR code of the receipt contents under the total
+pub const RECEIPT_QR: bool = true;
+pub const QR_EC_LEVEL: EcLevel = EcLevel::L;
+/// Largest size of one QR module in dots, smaller if the code won't fit
+pub const QR_MODULE_SIZE: u16 = 4;
+
+/// How barcodes are printed
+pub enum BarcodeOutput {
+    /// Drawn into the framebuffer, works on any printer
+    Raster,
+    /// The printer's `GS k` command, for printers with barcode firmware
+    Native,
+}
+
+pub const BARCODE_OUTPUT: BarcodeOutput = BarcodeOutput::Raster;
+/// Print the receipt number as a Code 128 under the total
+pub const RECEIPT_BARCODE: bool = true;
+/// Narrowest bar, in dots
+pub const BARCODE_MODULE: u16 = 2;
+/// Bar height in dots, at most the framebuffer height and 255
+pub const BARCODE_HEIGHT: u16 = 80;
+
+/// Most serial barcode scanners default to this
+pub const SCANNER_BAUD: u32 = 9600;
+
+/// Channel order of the LED strip: `Grbw` for SK6812 RGBW, `Grb` for
+/// WS2812 and other RGB parts
+pub type LedOrder = Grbw;
+/// Overall LED brightness out of 255, applied on top of every colour
+pub const LED_BRIGHTNESS: u8 = 255;
+/// Light pure whites from the white channel rather than mixing red, green
+/// and blue, which is brighter and draws less
+pub const LED_EXTRACT_WHITE: bool = true;
+/// The strip is dimmed to stay under this, for running off a battery or a
+/// weak USB port
+pub const LED_CURRENT_LIMIT_MA: u32 = 300;
@@ -0,0 +1,121 @@
+//! Receipt numbers, daily totals and the loyalty count, kept in flash across reboots.
+//!
+//! Every save appends a new slot rather than rewriting the old one, and the
+//! slot with the highest generation wins. The region has two sectors, and a
+//! sector is only erased when writing moves into it, so the previous values
+//! are still readable if power is lost during the erase.
+
+use defmt::warn;
+use embassy_rp::flash::ERASE_SIZE;
+
+use crate::storage::{Storage, COUNTERS};
+
+const FIELDS: usize = 6;
+/// Generation, fields, checksum
+const SLOT_SIZE: u32 = (FIELDS as u32 + 2) * 4;
+const SLOTS_PER_SECTOR: u32 = ERASE_SIZE as u32 / SLOT_SIZE;
+
+#[derive(Clone, Copy, Default)]
+pub struct Counters {
+    /// Last receipt number issued, never reset
+    pub receipt: u32,
+    /// Receipts issued since the last Z report
+    pub daily_receipts: u32,
+    /// Sum of the totals since the last Z report
+    pub daily_takings: u32,
+    /// Completed transactions, never reset, for the loyalty scheme
+    pub customers: u32,
+}
+
+impl Counters {
+    fn to_fields(self) -> [u32; FIELDS] {
+        let mut fields = [0; FIELDS];
+        fields[0] = self.receipt;
+        fields[1] = self.daily_receipts;
+        fields[2] = self.daily_takings;
+        fields[3] = self.customers;
+        fields
+    }
+
+    fn from_fields(fields: &[u32; FIELDS]) -> Self {
+        Self {
+            receipt: fields[0],
+            daily_receipts: fields[1],
+            daily_takings: fields[2],
+            customers: fields[3],
+        }
+    }
+}
+
+fn checksum(generation: u32, fields: &[u32; FIELDS]) -> u32 {
+    fields
+        .iter()
+        .fold(generation ^ 0x5EC0_11D5, |acc, f| acc.rotate_left(5) ^ f)
+}
+
+pub struct CounterStore {
+    next_slot: u32,
+    generation: u32,
+}
+
+impl CounterStore {
+    /// Find the most recently saved counters
+    pub fn load(storage: &mut Storage) -> (Self, Counters) {
+        let slots = COUNTERS.size() / SLOT_SIZE;
+        let mut latest: Option<(u32, u32, Counters)> = None;
+
+        for slot in 0..slots {
+            let mut bytes = [0u8; SLOT_SIZE as usize];
+            if let Err(e) = storage.read(&COUNTERS, slot * SLOT_SIZE, &mut bytes) {
+                warn!("Counter read failed: {:?}", e);
+                continue;
+            }
+            let mut words = bytes
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+            let generation = words.next().unwrap();
+            let mut fields = [0u32; FIELDS];
+            for field in fields.iter_mut() {
+                *field = words.next().unwrap();
+            }
+            let check = words.next().unwrap();
+            if generation == u32::MAX || check != checksum(generation, &fields) {
+                continue;
+            }
+            if latest.map_or(true, |(_, g, _)| generation > g) {
+                latest = Some((slot, generation, Counters::from_fields(&fields)));
+            }
+        }
+
+        match latest {
+            Some((slot, generation, counters)) => (
+                Self { next_slot: (slot + 1) % slots, generation: generation + 1 },
+                counters,
+            ),
+            None => (Self { next_slot: 0, generation: 0 }, Counters::default()),
+        }
+    }
+
+    pub fn save(&mut self, storage: &mut Storage, counters: &Counters) {
+        if self.next_slot % SLOTS_PER_SECTOR == 0 {
+            if let Err(e) = storage.erase_sector(&COUNTERS, self.next_slot / SLOTS_PER_SECTOR) {
+                warn!("Counter erase failed: {:?}", e);
+            }
+        }
+
+        let fields = counters.to_fields();
+        let mut bytes = [0u8; SLOT_SIZE as usize];
+        let words = core::iter::once(self.generation)
+            .chain(fields.iter().copied())
+            .chain(core::iter::once(checksum(self.generation, &fields)));
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        if let Err(e) = storage.write(&COUNTERS, self.next_slot * SLOT_SIZE, &bytes) {
+            warn!("Counter write failed: {:?}", e);
+        }
+        self.next_slot = (self.next_slot + 1) % (COUNTERS.size() / SLOT_SIZE);
+        self.generation += 1;
+    }
+}
@@ -0,0 +1,19 @@
+//! Glyphs sliced from `gfx/font/digits.png` by the build script.
+//!
+//! To add a character, draw it onto the sheet and add a line to
+//! `gfx/font/digits.map`.
+
+use escpos_embedded::Image;
+
+pub struct Glyph {
+    pub ch: char,
+    /// Row of the glyph image that sits on the text baseline
+    pub baseline: u16,
+    pub image: Image<&'static [u8]>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/glyphs.rs"));
+
+pub fn glyph(c: char) -> Option<&'static Glyph> {
+    GLYPHS.iter().find(|g| g.ch == c)
+}
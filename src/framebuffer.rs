@@ -0,0 +1,100 @@
+use escpos_embedded::Image;
+
+pub trait Framebuffer {
+    fn clear(&mut self);
+    fn blit_image<U: AsRef<[u8]>>(&mut self, src: &Image<U>, x_offset: u16, y_offset: u16);
+
+    fn head(&self, rows: u16) -> Image<&[u8]>;
+    fn width(&self) -> u16;
+
+    /// Set or clear one pixel, ignoring anything outside the framebuffer
+    fn set_pixel(&mut self, x: u16, y: u16, ink: bool);
+
+    fn fill_rect(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        for dy in y..y.saturating_add(height) {
+            for dx in x..x.saturating_add(width) {
+                self.set_pixel(dx, dy, true);
+            }
+        }
+    }
+
+    /// Outline of a rectangle, with the border drawn inside its bounds
+    fn draw_rect(&mut self, x: u16, y: u16, width: u16, height: u16, thickness: u16) {
+        let thickness = thickness.min(width / 2).min(height / 2);
+        self.fill_rect(x, y, width, thickness);
+        self.fill_rect(x, y.saturating_add(height).saturating_sub(thickness), width, thickness);
+        self.fill_rect(x, y, thickness, height);
+        self.fill_rect(x.saturating_add(width).saturating_sub(thickness), y, thickness, height);
+    }
+
+    /// A horizontal line broken into `dash` long pieces
+    fn dashed_hline(&mut self, y: u16, thickness: u16, dash: u16) {
+        let width = self.width();
+        for x in (0..width).step_by(dash.max(1) as usize * 2) {
+            self.fill_rect(x, y, dash.min(width - x), thickness);
+        }
+    }
+}
+
+impl<const N: usize> Framebuffer for Image<[u8; N]> {
+    fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    fn head(&self, rows: u16) -> Image<&[u8]> {
+        Image {
+            width: self.width,
+            height: rows,
+            data: &self.data[..(rows as usize * self.width as usize / 8)],
+        }
+    }
+
+    fn set_pixel(&mut self, x: u16, y: u16, ink: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let stride = self.width.div_ceil(8);
+        let idx = (y as usize) * (stride as usize) + (x / 8) as usize;
+        let bit = 7 - (x % 8);
+        if ink {
+            self.data[idx] |= 1 << bit;
+        } else {
+            self.data[idx] &= !(1 << bit);
+        }
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn blit_image<U: AsRef<[u8]>>(&mut self, src: &Image<U>, x_offset: u16, y_offset: u16) {
+        let dest_stride = self.width.div_ceil(8);
+        let src_stride = src.width.div_ceil(8);
+        let src_data = src.data.as_ref();
+
+        for y in 0..src.height {
+            let dy = y + y_offset;
+            if dy >= self.height {
+                continue;
+            }
+
+            for x in 0..src.width {
+                let dx = x + x_offset;
+                if dx >= self.width {
+                    continue;
+                }
+
+                let src_byte = src_data[(y as usize) * (src_stride as usize) + (x / 8) as usize];
+                let src_bit = 7 - (x % 8);
+                let dest_idx = (dy as usize) * (dest_stride as usize) + (dx / 8) as usize;
+                let dest_bit = 7 - (dx % 8);
+                if ((src_byte >> src_bit) & 1) != 0 {
+                    self.data[dest_idx] |= 1 << dest_bit;
+                } else {
+                    self.data[dest_idx] &= !(1 << dest_bit);
+                }
+            }
+        }
+    }
+
+}
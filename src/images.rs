@@ -0,0 +1,11 @@
+//! Pictures from `gfx`, embedded at build time for printing on receipts.
+
+use defmt::Format;
+use escpos_embed_image::embed_images;
+
+embed_images!(
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Format)]
+    enum Images {
+        #[pattern("gfx/*.png")]
+    }
+);
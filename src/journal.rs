@@ -0,0 +1,335 @@
+//! Append-only log of the open transaction, so a basket survives the till
+//! being unplugged.
+//!
+//! Each record is 8 bytes: tag, checksum, a 16 bit argument and a 32 bit
+//! value. Records are only ever appended; the region is erased when a new
+//! transaction might not fit. A last record cut short by the power going
+//! just ends the log, but anything else unreadable means the whole journal
+//! is corrupt.
+
+use heapless::Vec;
+
+use crate::catalogue;
+use crate::basket::{LineItem, MAX_LINES};
+
+// Tests scan records from memory instead of flash
+#[cfg(not(test))]
+use {
+    crate::storage::{Storage, JOURNAL},
+    defmt::warn,
+};
+
+const RECORD_SIZE: u32 = 8;
+/// Space needed to log the largest possible transaction
+#[cfg(not(test))]
+const MAX_TRANSACTION_SIZE: u32 = (MAX_LINES as u32 + 2) * RECORD_SIZE;
+
+const TAG_BEGIN: u8 = 0x01;
+const TAG_LINE: u8 = 0x02;
+const TAG_END: u8 = 0x03;
+
+struct Record {
+    tag: u8,
+    arg: u16,
+    value: u32,
+}
+
+impl Record {
+    fn encode(&self) -> [u8; RECORD_SIZE as usize] {
+        let mut bytes = [0u8; RECORD_SIZE as usize];
+        bytes[0] = self.tag;
+        bytes[2..4].copy_from_slice(&self.arg.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes[1] = checksum(&bytes);
+        bytes
+    }
+
+    fn decode(bytes: &[u8; RECORD_SIZE as usize]) -> Option<Self> {
+        if bytes[1] != checksum(bytes) {
+            return None;
+        }
+        Some(Self {
+            tag: bytes[0],
+            arg: u16::from_le_bytes([bytes[2], bytes[3]]),
+            value: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+fn checksum(bytes: &[u8; RECORD_SIZE as usize]) -> u8 {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .fold(0xA5, |acc, (_, b)| acc ^ b)
+}
+
+/// What `Journal::open` found in flash
+pub enum Recovered {
+    Clean,
+    /// A transaction was started but never totalled or voided
+    Unfinished { receipt: u32, lines: Vec<LineItem, MAX_LINES> },
+    /// The journal couldn't be read, and has been erased
+    Corrupt,
+}
+
+/// How a scan of the journal stopped
+#[derive(Debug, PartialEq, Eq)]
+enum End {
+    /// At erased flash, or the end of the region
+    Clean,
+    /// At a record that fails its checksum with nothing but erased flash
+    /// after it. The power went while it was being written, so everything
+    /// before it is good.
+    Torn,
+    /// At something that can't be read, with more of the log after it
+    Corrupt,
+}
+
+struct Scan {
+    /// Offset of the record the scan stopped at
+    offset: u32,
+    end: End,
+    /// The transaction left open, and its lines
+    open: Option<(u32, Vec<LineItem, MAX_LINES>)>,
+}
+
+const ERASED: [u8; RECORD_SIZE as usize] = [0xFF; RECORD_SIZE as usize];
+
+/// Read the log from the start of a region `size` bytes long. `read`
+/// gives the record at an offset, or None if the flash couldn't be read.
+fn scan(mut read: impl FnMut(u32) -> Option<[u8; RECORD_SIZE as usize]>, size: u32) -> Scan {
+    let mut offset = 0;
+    let mut open: Option<(u32, Vec<LineItem, MAX_LINES>)> = None;
+
+    let end = loop {
+        if offset + RECORD_SIZE > size {
+            break End::Clean;
+        }
+        let Some(bytes) = read(offset) else {
+            break End::Corrupt;
+        };
+        if bytes == ERASED {
+            break End::Clean;
+        }
+        match Record::decode(&bytes) {
+            Some(Record { tag: TAG_BEGIN, value, .. }) => open = Some((value, Vec::new())),
+            Some(Record { tag: TAG_LINE, arg, value }) => {
+                let product = catalogue::product(arg as u8);
+                let (Some((_, lines)), Some(product)) = (&mut open, product) else {
+                    break End::Corrupt;
+                };
+                if lines.push(LineItem { image: product.image, price: value as u16 }).is_err() {
+                    break End::Corrupt;
+                }
+            }
+            Some(Record { tag: TAG_END, .. }) => open = None,
+            Some(_) => break End::Corrupt,
+            None => {
+                let rest_erased = (offset + RECORD_SIZE..size)
+                    .step_by(RECORD_SIZE as usize)
+                    .all(|at| read(at) == Some(ERASED));
+                break if rest_erased { End::Torn } else { End::Corrupt };
+            }
+        }
+        offset += RECORD_SIZE;
+    };
+    Scan { offset, end, open }
+}
+
+#[cfg(not(test))]
+pub struct Journal {
+    next: u32,
+}
+
+#[cfg(not(test))]
+impl Journal {
+    /// Find the end of the journal, and any transaction that was still open
+    pub fn open(storage: &mut Storage) -> (Self, Recovered) {
+        let read = |offset| {
+            let mut bytes = [0u8; RECORD_SIZE as usize];
+            storage.read(&JOURNAL, offset, &mut bytes).ok().map(|()| bytes)
+        };
+        let Scan { offset, end, open } = scan(read, JOURNAL.size());
+
+        let mut journal = Self { next: offset };
+        match end {
+            End::Clean => {}
+            End::Torn => {
+                // The torn record can't be written over, so log what came
+                // before it again from the start
+                warn!("Journal ends in a torn record at offset {}, rewriting", offset);
+                journal.reset(storage);
+                if let Some((receipt, lines)) = &open {
+                    journal.begin(storage, *receipt);
+                    for line in lines {
+                        journal.line(storage, line);
+                    }
+                }
+            }
+            End::Corrupt => {
+                warn!("Journal corrupt at offset {}, erasing", offset);
+                journal.reset(storage);
+                return (journal, Recovered::Corrupt);
+            }
+        }
+        match open {
+            Some((receipt, lines)) => (journal, Recovered::Unfinished { receipt, lines }),
+            None => (journal, Recovered::Clean),
+        }
+    }
+
+    pub fn begin(&mut self, storage: &mut Storage, receipt: u32) {
+        if self.next + MAX_TRANSACTION_SIZE > JOURNAL.size() {
+            self.reset(storage);
+        }
+        self.append(storage, Record { tag: TAG_BEGIN, arg: 0, value: receipt });
+    }
+
+    pub fn line(&mut self, storage: &mut Storage, item: &LineItem) {
+        let Some(id) = catalogue::product_id(item.image) else {
+            warn!("Not journalling unknown product {:?}", item.image);
+            return;
+        };
+        self.append(storage, Record { tag: TAG_LINE, arg: id as u16, value: item.price as u32 });
+    }
+
+    /// Mark the open transaction as totalled or voided
+    pub fn finish(&mut self, storage: &mut Storage) {
+        self.append(storage, Record { tag: TAG_END, arg: 0, value: 0 });
+    }
+
+    fn reset(&mut self, storage: &mut Storage) {
+        if let Err(e) = storage.erase(&JOURNAL) {
+            warn!("Journal erase failed: {:?}", e);
+        }
+        self.next = 0;
+    }
+
+    fn append(&mut self, storage: &mut Storage, record: Record) {
+        if self.next + RECORD_SIZE > JOURNAL.size() {
+            warn!("Journal full, dropping record");
+            return;
+        }
+        match storage.write(&JOURNAL, self.next, &record.encode()) {
+            Ok(()) => self.next += RECORD_SIZE,
+            Err(e) => warn!("Journal write failed: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::Images;
+
+    /// Journal flash with `records` written from the start
+    fn flash(records: &[[u8; RECORD_SIZE as usize]]) -> std::vec::Vec<u8> {
+        let mut flash = vec![0xFF; 4096];
+        for (i, record) in records.iter().enumerate() {
+            flash[i * 8..i * 8 + 8].copy_from_slice(record);
+        }
+        flash
+    }
+
+    fn scan_flash(flash: &[u8]) -> Scan {
+        scan(|offset| flash[offset as usize..][..8].try_into().ok(), flash.len() as u32)
+    }
+
+    fn begin(receipt: u32) -> [u8; 8] {
+        Record { tag: TAG_BEGIN, arg: 0, value: receipt }.encode()
+    }
+
+    fn line(id: u16, price: u32) -> [u8; 8] {
+        Record { tag: TAG_LINE, arg: id, value: price }.encode()
+    }
+
+    fn end() -> [u8; 8] {
+        Record { tag: TAG_END, arg: 0, value: 0 }.encode()
+    }
+
+    fn lines(scan: &Scan) -> std::vec::Vec<(u32, Images, u16)> {
+        let (receipt, lines) = scan.open.as_ref().expect("a transaction left open");
+        lines.iter().map(|line| (*receipt, line.image, line.price)).collect()
+    }
+
+    #[test]
+    fn finished_transactions_leave_nothing_open() {
+        let scan = scan_flash(&flash(&[begin(1), line(0, 2), end(), begin(2), end()]));
+        assert_eq!(scan.end, End::Clean);
+        assert_eq!(scan.offset, 40);
+        assert!(scan.open.is_none());
+    }
+
+    #[test]
+    fn unfinished_transaction_is_recovered() {
+        let scan = scan_flash(&flash(&[begin(1), end(), begin(2), line(0, 2), line(7, 8)]));
+        assert_eq!(scan.end, End::Clean);
+        assert_eq!(lines(&scan), [(2, Images::Banana, 2), (2, Images::Pie, 8)]);
+    }
+
+    #[test]
+    fn torn_last_record_ends_the_log() {
+        // Only some bytes of the last line were programmed
+        let mut torn = line(3, 4);
+        torn[4..].fill(0xFF);
+        let scan = scan_flash(&flash(&[begin(5), line(0, 2), line(1, 1), torn]));
+        assert_eq!(scan.end, End::Torn);
+        assert_eq!(scan.offset, 24);
+        assert_eq!(lines(&scan), [(5, Images::Banana, 2), (5, Images::Juice, 1)]);
+
+        // Or all of them, with the wrong checksum
+        let mut torn = end();
+        torn[1] ^= 0x10;
+        let scan = scan_flash(&flash(&[begin(5), line(0, 2), torn]));
+        assert_eq!(scan.end, End::Torn);
+        assert_eq!(lines(&scan), [(5, Images::Banana, 2)]);
+    }
+
+    #[test]
+    fn bad_record_in_the_middle_is_corrupt() {
+        let mut bad = line(1, 1);
+        bad[1] ^= 0x10;
+        let scan = scan_flash(&flash(&[begin(5), bad, line(0, 2)]));
+        assert_eq!(scan.end, End::Corrupt);
+        assert_eq!(scan.offset, 8);
+
+        // Even with erased flash straight after it
+        let mut flash = flash(&[begin(5), bad]);
+        flash[1000..1008].copy_from_slice(&end());
+        assert_eq!(scan_flash(&flash).end, End::Corrupt);
+    }
+
+    #[test]
+    fn records_that_make_no_sense_are_corrupt() {
+        // A line outside a transaction
+        assert_eq!(scan_flash(&flash(&[line(0, 2)])).end, End::Corrupt);
+        // A product that isn't in the catalogue
+        assert_eq!(scan_flash(&flash(&[begin(1), line(200, 2)])).end, End::Corrupt);
+        // A tag that doesn't exist
+        let unknown = Record { tag: 0x7F, arg: 0, value: 0 }.encode();
+        assert_eq!(scan_flash(&flash(&[begin(1), unknown])).end, End::Corrupt);
+        // More lines than a basket holds
+        let mut records = vec![begin(1)];
+        records.extend([line(0, 2); MAX_LINES + 1]);
+        assert_eq!(scan_flash(&flash(&records)).end, End::Corrupt);
+    }
+
+    #[test]
+    fn unreadable_flash_is_corrupt() {
+        let scan = scan(|offset| (offset < 16).then(|| begin(1)), 4096);
+        assert_eq!(scan.end, End::Corrupt);
+        assert_eq!(scan.offset, 16);
+    }
+
+    #[test]
+    fn full_journal_ends_at_the_region() {
+        let mut records = std::vec::Vec::new();
+        for receipt in 0..256 {
+            records.extend([begin(receipt), end()]);
+        }
+        let scan = scan_flash(&flash(&records));
+        assert_eq!(scan.end, End::Clean);
+        assert_eq!(scan.offset, 4096);
+    }
+}
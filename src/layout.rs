@@ -0,0 +1,153 @@
+use heapless::Vec;
+
+use crate::font::{self, Glyph};
+use crate::framebuffer::Framebuffer;
+
+/// Enough for a u32 with separators, a decimal point, a sign, prefix and suffix
+const MAX_CHARS: usize = 20;
+
+/// How a number should be rendered with the digit glyphs
+pub struct NumberStyle {
+    /// Drawn before the digits, after any minus sign (e.g. '£')
+    pub prefix: Option<char>,
+    /// Drawn after the digits
+    pub suffix: Option<char>,
+    /// Drawn between each group of three digits
+    pub thousands: Option<char>,
+    /// Digits after the decimal point, so 2 draws 150 as 1.50
+    pub decimals: u8,
+    /// Gap between glyphs, in pixels
+    pub spacing: u16,
+}
+
+pub const PRICE: NumberStyle = NumberStyle {
+    prefix: Some('£'),
+    suffix: None,
+    thousands: Some(','),
+    decimals: 0,
+    spacing: 5,
+};
+
+pub const QUANTITY: NumberStyle = NumberStyle {
+    prefix: Some('x'),
+    suffix: None,
+    thousands: None,
+    decimals: 0,
+    spacing: 5,
+};
+
+pub const RECEIPT_NUMBER: NumberStyle = NumberStyle {
+    prefix: Some('#'),
+    suffix: None,
+    thousands: None,
+    decimals: 0,
+    spacing: 5,
+};
+
+fn glyph(c: char) -> &'static Glyph {
+    match font::glyph(c) {
+        Some(glyph) => glyph,
+        None => core::panic!("Unsupported character"),
+    }
+}
+
+/// The glyphs for `value`, rightmost first
+fn glyphs_rev(value: i32, style: &NumberStyle) -> Vec<&'static Glyph, MAX_CHARS> {
+    let mut glyphs = Vec::new();
+    let mut push = |c: char| {
+        // MAX_CHARS covers the longest possible i32 so this can't overflow
+        let _ = glyphs.push(glyph(c));
+    };
+
+    if let Some(suffix) = style.suffix {
+        push(suffix);
+    }
+
+    let mut remaining = value.unsigned_abs();
+    if style.decimals > 0 {
+        for _ in 0..style.decimals {
+            push(char::from_digit(remaining % 10, 10).unwrap());
+            remaining /= 10;
+        }
+        push('.');
+    }
+
+    let mut digits = 0;
+    loop {
+        if digits > 0 && digits % 3 == 0 {
+            if let Some(separator) = style.thousands {
+                push(separator);
+            }
+        }
+        push(char::from_digit(remaining % 10, 10).unwrap());
+        remaining /= 10;
+        digits += 1;
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    if let Some(prefix) = style.prefix {
+        push(prefix);
+    }
+    if value < 0 {
+        push('-');
+    }
+    glyphs
+}
+
+fn width_of(glyphs: &[&'static Glyph], spacing: u16) -> u16 {
+    let gaps = glyphs.len().saturating_sub(1) as u16 * spacing;
+    glyphs.iter().map(|g| g.image.width).sum::<u16>() + gaps
+}
+
+/// Draw glyphs leftwards from `right`, dropping any that don't fit
+fn draw_rev<F: Framebuffer>(fb: &mut F, glyphs: &[&'static Glyph], spacing: u16, right: u16, baseline: u16) {
+    let mut cur_x = right;
+    for (i, glyph) in glyphs.iter().enumerate() {
+        if i > 0 {
+            cur_x = cur_x.saturating_sub(spacing);
+        }
+        let Some(x) = cur_x.checked_sub(glyph.image.width) else {
+            break;
+        };
+        fb.blit_image(&glyph.image, x, baseline.saturating_sub(glyph.baseline));
+        cur_x = x;
+    }
+}
+
+/// Width in pixels that `draw_number` will use for `value`
+pub fn number_width(value: i32, style: &NumberStyle) -> u16 {
+    width_of(&glyphs_rev(value, style), style.spacing)
+}
+
+/// Draw `value` right-aligned so that its last glyph ends at `right`, with
+/// the glyph baselines on row `baseline`.
+///
+/// Returns the width used, which is the same as `number_width`. Anything
+/// that would fall off the left edge of the framebuffer is dropped.
+pub fn draw_number<F: Framebuffer>(
+    fb: &mut F,
+    value: i32,
+    style: &NumberStyle,
+    right: u16,
+    baseline: u16,
+) -> u16 {
+    let glyphs = glyphs_rev(value, style);
+    draw_rev(fb, &glyphs, style.spacing, right, baseline);
+    width_of(&glyphs, style.spacing)
+}
+
+/// Width in pixels that `draw_text` will use for `text`
+pub fn text_width(text: &str, spacing: u16) -> u16 {
+    let glyphs: Vec<&'static Glyph, MAX_CHARS> = text.chars().rev().map(glyph).take(MAX_CHARS).collect();
+    width_of(&glyphs, spacing)
+}
+
+/// Draw a string of glyph characters right-aligned, in the same way as
+/// `draw_number`, returning the width used.
+pub fn draw_text<F: Framebuffer>(fb: &mut F, text: &str, spacing: u16, right: u16, baseline: u16) -> u16 {
+    let glyphs: Vec<&'static Glyph, MAX_CHARS> = text.chars().rev().map(glyph).take(MAX_CHARS).collect();
+    draw_rev(fb, &glyphs, spacing, right, baseline);
+    width_of(&glyphs, spacing)
+}
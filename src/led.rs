@@ -1,28 +1,416 @@
-use defmt::info;
-use embassy_rp::{dma, interrupt::typelevel::Binding, pio::{self, InterruptHandler}, Peri};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use core::cell::{Cell, RefCell};
 
-use crate::sk6812::{PioSk6812, PioSk6812Program};
+use defmt::Format;
+use embassy_sync::{blocking_mutex::{raw::CriticalSectionRawMutex, Mutex}, signal::Signal};
+use embassy_time::{Duration, Instant};
 
+use crate::color::{Color, Hsv};
 
-pub use crate::sk6812::RGBW;
+// Only the task driving the strip needs the hardware
+#[cfg(not(test))]
+use {
+    crate::{color, config, sk6812::{ColorOrder, PioSk6812, PioSk6812Program}},
+    defmt::info,
+    embassy_futures::select::{select, Either},
+    embassy_rp::{dma, interrupt::typelevel::Binding, pio::{self, InterruptHandler}, Peri},
+    embassy_time::{Ticker, Timer},
+};
 
-pub enum LedState {
-    Color(RGBW),
-    Default,
-    Off,
-    Noop,
+/// How often animated patterns are redrawn
+#[cfg(not(test))]
+const FRAME_PERIOD: Duration = Duration::from_millis(20);
+/// What the status pixel shows when no layer has anything on it. The key
+/// pixels go dark instead.
+const DEFAULT_COLOR: Color = Color::rgb(0, 55, 0);
+
+/// Pixels on the strip: the status LED first, then one under each key
+pub const PIXELS: usize = 9;
+pub const STATUS_PIXEL: usize = 0;
+/// Position on the strip of the pixel under each produce key, in key order
+pub const KEY_PIXELS: [usize; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+/// A set of pixels on the strip to show a pattern on. Patterns that move,
+/// like `Chase`, run across the set in strip order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Pixels(u16);
+
+impl Pixels {
+    pub const STATUS: Pixels = Pixels(1 << STATUS_PIXEL);
+    pub const KEYS: Pixels = {
+        let mut mask = 0;
+        let mut i = 0;
+        while i < KEY_PIXELS.len() {
+            mask |= 1 << KEY_PIXELS[i];
+            i += 1;
+        }
+        Pixels(mask)
+    };
+    pub const ALL: Pixels = Pixels((1 << PIXELS) - 1);
+
+    /// The pixel under a produce key, counting from 0. Nothing for keys
+    /// without one.
+    pub const fn key(key: u8) -> Pixels {
+        if (key as usize) < KEY_PIXELS.len() {
+            Pixels(1 << KEY_PIXELS[key as usize])
+        } else {
+            Pixels(0)
+        }
+    }
+
+    pub const fn union(self, other: Pixels) -> Pixels {
+        Pixels(self.0 | other.0)
+    }
+
+    fn contains(&self, pixel: usize) -> bool {
+        self.0 >> pixel & 1 != 0
+    }
+
+    fn len(&self) -> u8 {
+        self.0.count_ones() as u8
+    }
+}
+
+/// Things that want the LED, lowest priority first. Each pixel shows the
+/// highest layer with something on it, and its default if none do.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// What the till is doing, idle or mid transaction
+    Base,
+    /// Short responses to key presses and the like
+    Feedback,
+    /// Something is wrong with the hardware
+    Fault,
+}
+const LAYERS: usize = 3;
+
+/// A pattern being shown on one pixel of a layer
+#[derive(Clone, Copy)]
+struct Active {
+    pattern: Pattern,
+    started: Instant,
+    expires: Option<Instant>,
+    /// Where the pixel is in the set the pattern was given to
+    index: u8,
+    count: u8,
+}
+
+/// One step of a `Pattern::Sequence`, faded into from the step before
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub color: Color,
+    pub duration: Duration,
+}
+
+/// Something for the LED to show, worked out afresh for every frame
+#[derive(Clone, Copy)]
+pub enum Pattern {
+    Solid(Color),
+    /// Fade between two colours, then stay on the second
+    Fade { from: Color, to: Color, duration: Duration },
+    /// Breathe in and out once per `period`
+    Pulse { color: Color, period: Duration },
+    /// Light up and fade out over `duration`, then finish
+    Flash { color: Color, duration: Duration },
+    /// Flash a number of times, then finish
+    Blink { color: Color, times: u8, on: Duration, off: Duration },
+    /// Go round the colour wheel once per `period`
+    Rainbow { period: Duration },
+    /// A lit pixel running along the set once per `period`, trailing
+    /// `tail` dimmer pixels behind it
+    Chase { color: Color, period: Duration, tail: u8 },
+    /// Step through keyframes, finishing at the end unless `repeat` is set
+    Sequence { frames: &'static [Keyframe], repeat: bool },
+    /// A blink code, `long` long blinks then `short` short ones, repeating
+    /// until cleared
+    Code { color: Color, long: u8, short: u8 },
+}
+
+const CODE_LONG: Duration = Duration::from_millis(600);
+const CODE_SHORT: Duration = Duration::from_millis(200);
+/// Dark time after each blink of a code
+const CODE_GAP: Duration = Duration::from_millis(300);
+/// Dark time before a code starts again
+const CODE_PAUSE: Duration = Duration::from_millis(1500);
+
+/// Something wrong with the hardware, shown on every pixel as a red blink
+/// code until it's cleared. The long blinks say which part is at fault:
+///
+/// | Fault                  | Long | Short |
+/// |------------------------|------|-------|
+/// | Printer not responding | 1    | 1     |
+/// | Paper out              | 1    | 2     |
+/// | UART error             | 1    | 3     |
+/// | SD card missing        | 2    | 1     |
+/// | Bad config             | 2    | 2     |
+/// | Flash journal corrupt  | 2    | 3     |
+/// | Low battery            | 3    | 1     |
+///
+/// A fault stays up until that fault is cleared. When several are up at
+/// once the one highest in the table shows, and the next takes over once
+/// it clears.
+///
+/// The till has no way yet to notice a silent printer, paper out, a
+/// missing SD card, bad config or a flat battery, so only UART errors and
+/// a corrupt journal are raised for now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Fault {
+    PrinterNotResponding,
+    PaperOut,
+    UartError,
+    SdCardMissing,
+    BadConfig,
+    JournalCorrupt,
+    LowBattery,
+}
+
+impl Fault {
+    /// Every fault, in the order they take turns to show
+    const ALL: [Fault; 7] = [
+        Fault::PrinterNotResponding,
+        Fault::PaperOut,
+        Fault::UartError,
+        Fault::SdCardMissing,
+        Fault::BadConfig,
+        Fault::JournalCorrupt,
+        Fault::LowBattery,
+    ];
+
+    /// Long and short blinks
+    pub const fn code(self) -> (u8, u8) {
+        match self {
+            Fault::PrinterNotResponding => (1, 1),
+            Fault::PaperOut => (1, 2),
+            Fault::UartError => (1, 3),
+            Fault::SdCardMissing => (2, 1),
+            Fault::BadConfig => (2, 2),
+            Fault::JournalCorrupt => (2, 3),
+            Fault::LowBattery => (3, 1),
+        }
+    }
+
+    pub const fn pattern(self) -> Pattern {
+        let (long, short) = self.code();
+        Pattern::Code { color: Color::rgb(188, 0, 0), long, short }
+    }
+}
+
+/// The faults that are up, one bit each in `Fault::ALL` order
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Faults(u8);
+
+impl Faults {
+    fn with(self, fault: Fault) -> Faults {
+        Faults(self.0 | 1 << fault as u8)
+    }
+
+    fn without(self, fault: Fault) -> Faults {
+        Faults(self.0 & !(1 << fault as u8))
+    }
+
+    /// The fault to show out of those that are up
+    fn shown(self) -> Option<Fault> {
+        (self.0 != 0).then(|| Fault::ALL[self.0.trailing_zeros() as usize])
+    }
+}
+
+/// Whether a blink code is lit `elapsed` into it
+pub fn code_lit(long: u8, short: u8, elapsed: Duration) -> bool {
+    let (on_long, on_short) = (CODE_LONG.as_micros(), CODE_SHORT.as_micros());
+    let gap = CODE_GAP.as_micros();
+    let cycle = long as u64 * (on_long + gap) + short as u64 * (on_short + gap) + CODE_PAUSE.as_micros();
+    let mut t = elapsed.as_micros() % cycle;
+    let blinks = core::iter::repeat_n(on_long, long as usize).chain(core::iter::repeat_n(on_short, short as usize));
+    for on in blinks {
+        if t < on {
+            return true;
+        }
+        if t < on + gap {
+            return false;
+        }
+        t -= on + gap;
+    }
+    false
+}
+
+impl Pattern {
+    fn is_animated(&self) -> bool {
+        !matches!(self, Pattern::Solid(_))
+    }
+
+    /// The colour `elapsed` into the pattern for pixel `index` of the
+    /// `count` it's shown on, or None once it has finished
+    fn render(&self, elapsed: Duration, index: u8, count: u8) -> Option<Color> {
+        let t = elapsed.as_micros();
+        match *self {
+            Pattern::Solid(color) => Some(color),
+            Pattern::Fade { from, to, duration } => Some(from.lerp(to, fraction(t, duration.as_micros()))),
+            Pattern::Pulse { color, period } => {
+                let period = period.as_micros().max(1);
+                let phase = fraction(t % period, period) * 2;
+                let level = if phase > 256 { 512 - phase } else { phase };
+                // Squared so it lingers near off, which looks more like breathing
+                Some(color.scale(level * level / 256))
+            }
+            Pattern::Flash { color, duration } => {
+                let duration = duration.as_micros();
+                (t < duration).then(|| color.scale(256 - fraction(t, duration)))
+            }
+            Pattern::Blink { color, times, on, off } => {
+                let cycle = (on + off).as_micros().max(1);
+                if t / cycle >= times as u64 {
+                    None
+                } else if t % cycle < on.as_micros() {
+                    Some(color)
+                } else {
+                    Some(Color::BLACK)
+                }
+            }
+            Pattern::Rainbow { period } => {
+                let period = period.as_micros().max(1);
+                let h = (fraction(t % period, period) * 360 / 256) as u16;
+                Some(Color::from_hsv(Hsv { h, s: 255, v: 255 }))
+            }
+            Pattern::Chase { color, period, tail } => {
+                let period = period.as_micros().max(1);
+                let count = count.max(1) as u32;
+                let head = (fraction(t % period, period) * count / 256) as u8;
+                let behind = (head as u32 + count - index as u32) % count;
+                if behind > tail as u32 {
+                    Some(Color::BLACK)
+                } else {
+                    Some(color.scale(256 - behind * 256 / (tail as u32 + 1)))
+                }
+            }
+            Pattern::Sequence { frames, repeat } => {
+                let total: u64 = frames.iter().map(|f| f.duration.as_micros()).sum();
+                if frames.is_empty() || (!repeat && t >= total) {
+                    return None;
+                }
+                let mut t = t % total.max(1);
+                for (i, frame) in frames.iter().enumerate() {
+                    let duration = frame.duration.as_micros();
+                    if t < duration {
+                        let previous = match i {
+                            0 if repeat => frames[frames.len() - 1].color,
+                            0 => frame.color,
+                            _ => frames[i - 1].color,
+                        };
+                        return Some(previous.lerp(frame.color, fraction(t, duration)));
+                    }
+                    t -= duration;
+                }
+                frames.last().map(|f| f.color)
+            }
+            Pattern::Code { color, long, short } => {
+                Some(if code_lit(long, short, elapsed) { color } else { Color::BLACK })
+            }
+        }
+    }
+}
+
+/// How far `t` is through `duration`, from 0 to 256
+fn fraction(t: u64, duration: u64) -> u32 {
+    if t >= duration {
+        256
+    } else {
+        (t * 256 / duration) as u32
+    }
 }
 
-// pub static LED_STATE: Signal<CriticalSectionRawMutex, LedState> = Signal::new();
-pub static LED_STATE: embassy_sync::channel::Channel<
-    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-    LedState,
-    1,
-> = embassy_sync::channel::Channel::new();
+type Layers = [[Option<Active>; PIXELS]; LAYERS];
 
+/// What every layer is showing. Anything can change it without waiting,
+/// and the LED task works out the frame from it.
+static LAYER_STATE: Mutex<CriticalSectionRawMutex, RefCell<Layers>> =
+    Mutex::new(RefCell::new([[None; PIXELS]; LAYERS]));
+/// Faults raised and not yet cleared
+static FAULTS: Mutex<CriticalSectionRawMutex, Cell<Faults>> = Mutex::new(Cell::new(Faults(0)));
+/// Wakes the LED task to redraw after a layer changes
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
+/// Show a pattern on some pixels of a layer, replacing whatever was there.
+/// The layer clears itself once the pattern finishes or `timeout` passes.
+/// This never waits, so it's fine to call from anywhere.
+pub fn set(layer: Layer, pixels: Pixels, pattern: Pattern, timeout: Option<Duration>) {
+    let started = Instant::now();
+    let expires = timeout.map(|timeout| started + timeout);
+    let count = pixels.len();
+    LAYER_STATE.lock(|layers| {
+        let mut index = 0;
+        for (pixel, slot) in layers.borrow_mut()[layer as usize].iter_mut().enumerate() {
+            if pixels.contains(pixel) {
+                *slot = Some(Active { pattern, started, expires, index, count });
+                index += 1;
+            }
+        }
+    });
+    CHANGED.signal(());
+}
 
+pub fn clear(layer: Layer, pixels: Pixels) {
+    LAYER_STATE.lock(|layers| {
+        for (pixel, slot) in layers.borrow_mut()[layer as usize].iter_mut().enumerate() {
+            if pixels.contains(pixel) {
+                *slot = None;
+            }
+        }
+    });
+    CHANGED.signal(());
+}
+
+pub fn raise(fault: Fault) {
+    change_faults(|faults| faults.with(fault));
+}
+
+/// Clear a fault once it's fixed. Any others that are up stay up.
+pub fn clear_fault(fault: Fault) {
+    change_faults(|faults| faults.without(fault));
+}
+
+fn change_faults(change: impl FnOnce(Faults) -> Faults) {
+    FAULTS.lock(|faults| {
+        let before = faults.get();
+        let after = change(before);
+        faults.set(after);
+        // Raising the same fault again doesn't restart its code
+        match after.shown() {
+            shown if shown == before.shown() => {}
+            Some(fault) => set(Layer::Fault, Pixels::ALL, fault.pattern(), None),
+            None => clear(Layer::Fault, Pixels::ALL),
+        }
+    });
+}
+
+/// The colour of each pixel at `now`, and whether any of them are moving.
+/// Layers that have finished or timed out are dropped on the way.
+fn render(layers: &mut Layers, now: Instant) -> ([Color; PIXELS], bool) {
+    for slot in layers.iter_mut().flatten() {
+        if slot.is_some_and(|active| active.expires.is_some_and(|at| now >= at)) {
+            *slot = None;
+        }
+    }
+
+    let mut frame = [Color::BLACK; PIXELS];
+    let mut animated = false;
+    for (pixel, color) in frame.iter_mut().enumerate() {
+        *color = if pixel == STATUS_PIXEL { DEFAULT_COLOR } else { Color::BLACK };
+        for layer in layers.iter_mut().rev() {
+            let Some(active) = layer[pixel] else { continue };
+            // Saturating, as a layer can be set after `now` was read
+            match active.pattern.render(now.saturating_duration_since(active.started), active.index, active.count) {
+                Some(c) => {
+                    *color = c;
+                    animated |= active.pattern.is_animated();
+                    break;
+                }
+                None => layer[pixel] = None,
+            }
+        }
+    }
+    (frame, animated)
+}
+
+#[cfg(not(test))]
 pub struct Led<
     'a,
     PIO: pio::Instance,
@@ -35,6 +423,7 @@ pub struct Led<
     data_pin: Peri<'a, DATA>,
 }
 
+#[cfg(not(test))]
 impl<
         'a,
         PIO: pio::Instance,
@@ -62,36 +451,222 @@ impl<
         } = pio::Pio::new(self.pio_unit, irqs);
 
         let program = PioSk6812Program::new(&mut common);
-        let mut sk = PioSk6812::<PIO, 1, 1>::new(
+        let mut sk = PioSk6812::<PIO, 1, PIXELS, config::LedOrder>::new(
             &mut common,
             sm1,
             self.dma,
             self.data_pin,
             &program,
         );
+        color::set_brightness(config::LED_BRIGHTNESS);
         info!("LED Configured");
-        let startup: &[RGBW;1] = &[RGBW::new(0, 10, 0, 0)];
-        sk.write(startup).await;
-        info!("Led Initialized");
 
+        let mut ticker = Ticker::every(FRAME_PERIOD);
         loop {
-            match LED_STATE.receive().await {
-                LedState::Color(color) => {
-                    let slice: &[RGBW;1] = &[color];
-                    sk.write(slice).await;
-                },
-                LedState::Default => {
-                    let slice: &[RGBW;1] = &[RGBW::new(0, 10, 0, 0)];
-                    sk.write(slice).await;
-                },
-                LedState::Off => {
-                    let slice: &[RGBW;1] = &[RGBW::black()];
-                    sk.write(slice).await;
-                },
-                LedState::Noop => {
-                    // Do nothing
-                }
+            let (frame, animated, expires) = LAYER_STATE.lock(|layers| {
+                let mut layers = layers.borrow_mut();
+                let (frame, animated) = render(&mut layers, Instant::now());
+                let expires = layers.iter().flatten().flatten().filter_map(|active| active.expires).min();
+                (frame, animated, expires)
+            });
+            let mut corrected = frame;
+            let extract_white = config::LED_EXTRACT_WHITE && config::LedOrder::BITS == 32;
+            color::correct(&mut corrected, extract_white, config::LED_CURRENT_LIMIT_MA);
+            sk.write(&corrected).await;
+
+            // Static colours only need redrawing when a layer changes or
+            // times out
+            let woken = if animated {
+                matches!(select(CHANGED.wait(), ticker.next()).await, Either::First(()))
+            } else if let Some(at) = expires {
+                matches!(select(CHANGED.wait(), Timer::at(at)).await, Either::First(()))
+            } else {
+                CHANGED.wait().await;
+                true
+            };
+            if woken {
+                ticker.reset();
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A blink code as runs of lit or dark and how long each lasts in
+    /// milliseconds, over the first `total_ms` of it
+    fn runs(long: u8, short: u8, total_ms: u64) -> Vec<(bool, u64)> {
+        let mut runs: Vec<(bool, u64)> = Vec::new();
+        for ms in (0..total_ms).step_by(10) {
+            let lit = code_lit(long, short, Duration::from_millis(ms));
+            match runs.last_mut() {
+                Some((last, length)) if *last == lit => *length += 10,
+                _ => runs.push((lit, 10)),
             }
         }
+        runs
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn code_blinks_long_then_short_then_pauses() {
+        // UART error, one long and three short
+        assert_eq!(
+            runs(1, 3, 3900),
+            [
+                (true, 600), (false, 300),
+                (true, 200), (false, 300),
+                (true, 200), (false, 300),
+                (true, 200), (false, 300 + 1500),
+            ]
+        );
+        // Corrupt journal, two long and three short
+        assert_eq!(
+            runs(2, 3, 4800),
+            [
+                (true, 600), (false, 300),
+                (true, 600), (false, 300),
+                (true, 200), (false, 300),
+                (true, 200), (false, 300),
+                (true, 200), (false, 300 + 1500),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_repeats() {
+        let once = runs(2, 1, 3800);
+        assert_eq!(once.iter().map(|(_, ms)| ms).sum::<u64>(), 3800);
+        assert_eq!(runs(2, 1, 3 * 3800), [once.as_slice(); 3].concat());
+        // And carries on indefinitely
+        let hour = Duration::from_secs(3600);
+        for ms in (0..3800).step_by(10) {
+            let at = Duration::from_millis(ms);
+            assert_eq!(code_lit(2, 1, at), code_lit(2, 1, at + hour * 38));
+        }
+    }
+
+    #[test]
+    fn code_pattern_never_finishes() {
+        let pattern = Fault::UartError.pattern();
+        let red = Some(Color::rgb(188, 0, 0));
+        assert_eq!(pattern.render(Duration::from_millis(0), 0, 1), red);
+        assert_eq!(pattern.render(Duration::from_millis(700), 0, 1), Some(Color::BLACK));
+        assert_eq!(pattern.render(Duration::from_secs(3900 * 24), 0, 1), red);
+    }
+
+    #[test]
+    fn every_fault_has_its_own_code() {
+        let codes: Vec<_> = Fault::ALL.iter().map(|f| f.code()).collect();
+        for (i, code) in codes.iter().enumerate() {
+            assert!(!codes[i + 1..].contains(code));
+        }
+    }
+
+    const RED: Color = Color::rgb(255, 0, 0);
+    const BLUE: Color = Color::rgb(0, 0, 255);
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    /// A pattern on one pixel of its own, as `set` leaves it
+    fn active(pattern: Pattern, started: Instant, expires: Option<Instant>) -> Option<Active> {
+        Some(Active { pattern, started, expires, index: 0, count: 1 })
+    }
+
+    #[test]
+    fn empty_layers_show_the_defaults() {
+        let mut layers: Layers = [[None; PIXELS]; LAYERS];
+        let (frame, animated) = render(&mut layers, at(0));
+        assert_eq!(frame[STATUS_PIXEL], DEFAULT_COLOR);
+        for pixel in KEY_PIXELS {
+            assert_eq!(frame[pixel], Color::BLACK);
+        }
+        assert!(!animated);
+    }
+
+    #[test]
+    fn highest_layer_wins() {
+        let mut layers: Layers = [[None; PIXELS]; LAYERS];
+        layers[Layer::Base as usize][STATUS_PIXEL] = active(Pattern::Solid(RED), at(0), None);
+        layers[Layer::Fault as usize][STATUS_PIXEL] = active(Pattern::Solid(BLUE), at(0), None);
+        let (frame, animated) = render(&mut layers, at(10));
+        assert_eq!(frame[STATUS_PIXEL], BLUE);
+        assert!(!animated);
+
+        layers[Layer::Fault as usize][STATUS_PIXEL] = None;
+        assert_eq!(render(&mut layers, at(10)).0[STATUS_PIXEL], RED);
+    }
+
+    #[test]
+    fn timed_out_and_finished_layers_are_dropped() {
+        let mut layers: Layers = [[None; PIXELS]; LAYERS];
+        let key = KEY_PIXELS[0];
+        layers[Layer::Base as usize][key] = active(Pattern::Solid(RED), at(0), None);
+        layers[Layer::Feedback as usize][key] = active(Pattern::Solid(BLUE), at(0), Some(at(100)));
+        layers[Layer::Fault as usize][key] = active(
+            Pattern::Blink { color: BLUE, times: 1, on: Duration::from_millis(20), off: Duration::from_millis(20) },
+            at(0),
+            None,
+        );
+
+        // The blink is still going, so it shows and keeps the frames coming
+        let (frame, animated) = render(&mut layers, at(10));
+        assert_eq!(frame[key], BLUE);
+        assert!(animated);
+
+        // Once it's done, the feedback under it shows until it times out
+        let (frame, animated) = render(&mut layers, at(50));
+        assert_eq!(frame[key], BLUE);
+        assert!(!animated);
+        assert!(layers[Layer::Fault as usize][key].is_none());
+
+        let (frame, _) = render(&mut layers, at(100));
+        assert_eq!(frame[key], RED);
+        assert!(layers[Layer::Feedback as usize][key].is_none());
+    }
+
+    #[test]
+    fn fade_and_rainbow() {
+        let fade = Pattern::Fade { from: Color::BLACK, to: RED, duration: Duration::from_millis(100) };
+        assert_eq!(fade.render(Duration::from_millis(0), 0, 1), Some(Color::BLACK));
+        assert_eq!(fade.render(Duration::from_millis(50), 0, 1), Some(Color::rgb(127, 0, 0)));
+        // Stays on the second colour once it's there
+        assert_eq!(fade.render(Duration::from_secs(60), 0, 1), Some(RED));
+
+        let rainbow = Pattern::Rainbow { period: Duration::from_millis(360) };
+        assert_eq!(rainbow.render(Duration::from_millis(0), 0, 1), Some(RED));
+        assert_eq!(rainbow.render(Duration::from_millis(360), 0, 1), Some(RED));
+        assert_ne!(rainbow.render(Duration::from_millis(120), 0, 1), Some(RED));
+    }
+
+    #[test]
+    fn faults_in_table_order() {
+        for (i, fault) in Fault::ALL.iter().enumerate() {
+            assert_eq!(*fault as usize, i);
+        }
+    }
+
+    #[test]
+    fn clearing_a_fault_leaves_the_others() {
+        let faults = Faults::default().with(Fault::JournalCorrupt);
+        assert_eq!(faults.shown(), Some(Fault::JournalCorrupt));
+        // A printer write going through doesn't hide the journal
+        assert_eq!(faults.without(Fault::UartError).shown(), Some(Fault::JournalCorrupt));
+
+        let faults = faults.with(Fault::UartError);
+        assert_eq!(faults.shown(), Some(Fault::UartError));
+        assert_eq!(faults.without(Fault::UartError).shown(), Some(Fault::JournalCorrupt));
+        assert_eq!(faults.without(Fault::JournalCorrupt).shown(), Some(Fault::UartError));
+        assert_eq!(faults.without(Fault::UartError).without(Fault::JournalCorrupt).shown(), None);
+    }
+
+    #[test]
+    fn a_later_fault_doesnt_replace_an_earlier_one() {
+        let faults = Faults::default().with(Fault::PaperOut).with(Fault::LowBattery);
+        assert_eq!(faults.shown(), Some(Fault::PaperOut));
+        assert!(faults.with(Fault::PaperOut) == faults);
+        assert_eq!(faults.without(Fault::PaperOut).shown(), Some(Fault::LowBattery));
+    }
+}
@@ -1,35 +1,77 @@
-#![feature(impl_trait_in_assoc_type)]
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), feature(impl_trait_in_assoc_type))]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
+pub mod barcode;
+pub mod basket;
+pub mod catalogue;
+#[cfg(not(test))]
+pub mod clock;
+pub mod color;
+pub mod config;
+#[cfg(not(test))]
+pub mod counters;
+pub mod font;
+pub mod framebuffer;
+pub mod images;
+pub mod journal;
+pub mod layout;
 pub mod led;
+#[cfg(test)]
+mod pio_sim;
+#[cfg(not(test))]
 pub mod printer;
+pub mod promotions;
+pub mod qr;
+pub mod scanner;
 pub mod sk6812;
+#[cfg(not(test))]
 pub mod state;
+#[cfg(not(test))]
+pub mod storage;
+pub mod tax;
+#[cfg(not(test))]
+pub mod usb;
 
-use assign_resources::assign_resources;
-use embassy_time::Timer;
-use defmt::info;
-use embassy_executor::task;
-use embassy_time::Duration;
-use embassy_executor::Spawner;
-use embassy_rp::bind_interrupts;
-use embassy_rp::gpio::Input;
-use embassy_rp::peripherals;
-use embassy_rp::peripherals::PIO1;
-use embassy_rp::uart::Blocking;
-use embassy_rp::uart::Parity;
-use embassy_rp::uart::Uart;
-use embassy_rp::uart::{Config, DataBits, StopBits};
-use embassy_rp::Peri;
-use embedded_io::Write;
-use embassy_rp::pio::{InterruptHandler};
-use crate::printer::Images;
-use crate::state::InputEvent;
-use crate::state::INPUT_EVENTS;
-
-use {defmt_rtt as _, panic_probe as _};
+// Everything below runs the hardware, so none of it is built for tests
+#[cfg(not(test))]
+use {
+    assign_resources::assign_resources,
+    embassy_time::Timer,
+    defmt::{info, warn},
+    embassy_executor::task,
+    embassy_time::Duration,
+    embassy_time::with_timeout,
+    embassy_executor::Spawner,
+    embassy_rp::bind_interrupts,
+    embassy_rp::gpio::Input,
+    embassy_rp::peripherals,
+    embassy_rp::peripherals::PIO1,
+    embassy_rp::uart::Blocking,
+    embassy_rp::uart::BufferedUartRx,
+    embassy_rp::uart::Parity,
+    embassy_rp::uart::Uart,
+    embassy_rp::uart::{Config, DataBits, StopBits},
+    embassy_rp::Peri,
+    embassy_rp::rtc::Rtc,
+    embassy_rp::usb::Driver,
+    embedded_io::Write,
+    static_cell::StaticCell,
+    embassy_rp::pio::{InterruptHandler},
+    crate::catalogue::{Product, PRODUCTS},
+    crate::led::Fault,
+    crate::basket::InputEvent,
+    crate::state::INPUT_EVENTS,
+    crate::storage::Storage,
+    defmt_rtt as _,
+    panic_probe as _,
+};
 
+/// How long void or total have to be held to count as a long press
+#[cfg(not(test))]
+const LONG_PRESS: Duration = Duration::from_millis(1000);
+
+#[cfg(not(test))]
 assign_resources! {
     uart: UartResources {
         uart: UART1,
@@ -52,6 +94,23 @@ assign_resources! {
         void: PIN_16
     },
 
+    flash: FlashResources {
+        flash: FLASH,
+    },
+
+    clock: ClockResources {
+        rtc: RTC,
+    },
+
+    usb: UsbResources {
+        usb: USB,
+    },
+
+    scanner: ScannerResources {
+        uart: UART0,
+        rx_pin: PIN_1,
+    },
+
     led: LedResources {
         pio: PIO1,
         dma: DMA_CH4,
@@ -60,49 +119,92 @@ assign_resources! {
 
 }
 
+#[cfg(not(test))]
 bind_interrupts!(struct Irqs {
     PIO1_IRQ_0 => InterruptHandler<PIO1>;
+    USBCTRL_IRQ => embassy_rp::usb::InterruptHandler<peripherals::USB>;
+    UART0_IRQ => embassy_rp::uart::BufferedInterruptHandler<peripherals::UART0>;
 });
 
-pub struct UartWrap<'a>(Uart<'a, Blocking>);
+#[cfg(not(test))]
+pub struct UartWrap<'a> {
+    uart: Uart<'a, Blocking>,
+    /// A UART error is being shown on the LED
+    fault: bool,
+}
 
+#[cfg(not(test))]
 impl<'a> escpos_embedded::Write for UartWrap<'a> {
     type Error = embassy_rp::uart::Error;
 
+    /// A failed write is shown on the LED until one goes through, and
+    /// passed back so the printer driver drops the rest of that job
     fn write(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
-        self.0.blocking_write(buf)?;
-        self.0.flush()
+        match self.uart.blocking_write(buf).and_then(|()| self.uart.flush()) {
+            Err(e) => {
+                warn!("Printer UART error: {:?}", e);
+                led::raise(Fault::UartError);
+                self.fault = true;
+                return Err(e);
+            }
+            Ok(()) if self.fault => {
+                led::clear_fault(Fault::UartError);
+                self.fault = false;
+            }
+            Ok(()) => {}
+        }
+        Ok(())
     }
 }
+#[cfg(not(test))]
 impl<'a> escpos_embedded::Read for UartWrap<'a> {
     type Error = embassy_rp::uart::Error;
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.0.blocking_read(buf)?;
+        self.uart.blocking_read(buf)?;
         Ok(buf.len())
     }
 }
 
+#[cfg(not(test))]
 #[task]
 async fn led_task(led: LedResources) {
     let runner = led::Led::new(led.pio, led.dma, led.data_pin);
     runner.run(Irqs).await;
 }
 
+#[cfg(not(test))]
+#[task]
+async fn usb_task(driver: Driver<'static, peripherals::USB>) {
+    usb::run(driver).await;
+}
+
+#[cfg(not(test))]
+#[task]
+async fn scanner_task(scanner: ScannerResources) {
+    static RX_BUFFER: StaticCell<[u8; 64]> = StaticCell::new();
+    let mut uart_config = Config::default();
+    uart_config.baudrate = config::SCANNER_BAUD;
+    let rx = BufferedUartRx::new(scanner.uart, Irqs, scanner.rx_pin, RX_BUFFER.init([0; 64]), uart_config);
+    scanner::run(rx).await;
+}
+
+#[cfg(not(test))]
 #[task]
 async fn printer_driver(printer: escpos_embedded::Printer<UartWrap<'static>>) {
     printer::driver(printer).await;
 }
 
+#[cfg(not(test))]
 #[task(pool_size=8)]
-async fn produce_button_task(mut btn: Input<'static>, image: Images, price: u16) {
+async fn produce_button_task(mut btn: Input<'static>, product: &'static Product) {
     loop {
          btn.wait_for_any_edge().await;
         if btn.is_low() {
-            info!("Button pressed: {:?}", image);
+            info!("Button pressed: {:?}", product.image);
             INPUT_EVENTS.send(InputEvent::ProduceButtonPressed {
-                image: image,
-                price,
+                image: product.image,
+                price: product.price,
             }).await;
             Timer::after(Duration::from_millis(200)).await;
         }
@@ -112,13 +214,30 @@ async fn produce_button_task(mut btn: Input<'static>, image: Images, price: u16)
     }
 }
 
+/// Wait for a pressed button to be let go, returning false if it's held
+/// for LONG_PRESS
+#[cfg(not(test))]
+async fn wait_for_release(btn: &Input<'static>) -> bool {
+    with_timeout(LONG_PRESS, async {
+        while btn.is_low() {
+            Timer::after(Duration::from_millis(20)).await;
+        }
+    }).await.is_ok()
+}
+
+#[cfg(not(test))]
 #[task]
 async fn void_button_task(mut btn: Input<'static>) {
     loop {
         btn.wait_for_any_edge().await;
         if btn.is_low() {
-            info!("Void button pressed");
-            INPUT_EVENTS.send(InputEvent::VoidButtonPressed).await;
+            if wait_for_release(&btn).await {
+                info!("Void button pressed");
+                INPUT_EVENTS.send(InputEvent::VoidButtonPressed).await;
+            } else {
+                info!("Void button long pressed");
+                INPUT_EVENTS.send(InputEvent::VoidButtonLongPressed).await;
+            }
             Timer::after(Duration::from_millis(200)).await;
         }
         while btn.is_low() {
@@ -127,13 +246,19 @@ async fn void_button_task(mut btn: Input<'static>) {
     }
 }
 
+#[cfg(not(test))]
 #[task]
 async fn total_button_task(mut btn: Input<'static>) {
     loop {
         btn.wait_for_any_edge().await;
         if btn.is_low() {
-            info!("Total button pressed");
-            INPUT_EVENTS.send(InputEvent::TotalButtonPressed).await;
+            if wait_for_release(&btn).await {
+                info!("Total button pressed");
+                INPUT_EVENTS.send(InputEvent::TotalButtonPressed).await;
+            } else {
+                info!("Total button long pressed");
+                INPUT_EVENTS.send(InputEvent::TotalButtonLongPressed).await;
+            }
             Timer::after(Duration::from_millis(200)).await;
         }
         while btn.is_low() {
@@ -142,6 +267,7 @@ async fn total_button_task(mut btn: Input<'static>) {
     }
 }
 
+#[cfg(not(test))]
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
@@ -164,51 +290,47 @@ async fn main(spawner: Spawner) {
         uart_pins.cts_pin,
         config,
     );
-    let printer = escpos_embedded::Printer::new(UartWrap(uart));
+    clock::init(Rtc::new(r.clock.rtc));
+    spawner.spawn(usb_task(Driver::new(r.usb.usb, Irqs))).unwrap();
+    spawner.spawn(scanner_task(r.scanner)).unwrap();
+
+    let printer = escpos_embedded::Printer::new(UartWrap { uart, fault: false });
     spawner.spawn(printer_driver(printer)).unwrap();
     spawner.spawn(led_task(r.led)).unwrap();
 
-    spawner.spawn(state::main_state()).unwrap();
+    spawner.spawn(state::main_state(Storage::new(r.flash.flash))).unwrap();
 
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_1, embassy_rp::gpio::Pull::Up),
-        Images::Banana,
-        2
+        &PRODUCTS[0]
     )).unwrap();
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_2, embassy_rp::gpio::Pull::Up),
-        Images::Juice,
-        1
+        &PRODUCTS[1]
     )).unwrap();
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_3, embassy_rp::gpio::Pull::Up),
-        Images::Eggs,
-        3
+        &PRODUCTS[2]
     )).unwrap() ;
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_4, embassy_rp::gpio::Pull::Up),
-        Images::Cheese,
-        4
+        &PRODUCTS[3]
     )).unwrap() ;
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_5, embassy_rp::gpio::Pull::Up),
-        Images::Bread,
-        5
+        &PRODUCTS[4]
     )).unwrap() ;
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_6, embassy_rp::gpio::Pull::Up),
-        Images::Sberry,
-        6
+        &PRODUCTS[5]
     )).unwrap() ;
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_7, embassy_rp::gpio::Pull::Up),
-        Images::Chicken,
-        7
+        &PRODUCTS[6]
     )).unwrap() ;
     spawner.spawn(produce_button_task(
         Input::new(r.keys.key_8, embassy_rp::gpio::Pull::Up),
-        Images::Pie,
-        8
+        &PRODUCTS[7]
     )).unwrap() ;
     spawner.spawn(void_button_task(
         Input::new(r.keys.void, embassy_rp::gpio::Pull::Up)
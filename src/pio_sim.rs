@@ -0,0 +1,185 @@
+//! Just enough of an RP2040 PIO state machine to run the SK6812 program
+//! away from the chip and see the waveform it puts on its pin.
+//!
+//! It handles one side-set pin, autopull with the OSR shifting left, and
+//! the `jmp`, `out`, `mov` and `set` instructions the LED programs use.
+//! Anything else comes back as `Error::Unsupported`. It's only built for
+//! the tests.
+
+use heapless::Vec;
+use pio::Program;
+
+/// Most pin changes recorded in one run, enough for two 32 bit words
+const MAX_SEGMENTS: usize = 160;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An instruction the emulator doesn't know, as assembled
+    Unsupported(u16),
+    /// The program ran past `MAX_SEGMENTS` pin changes or never stalled
+    TooLong,
+}
+
+/// The pin held at one level for a number of PIO clock cycles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub high: bool,
+    pub cycles: u32,
+}
+
+/// What the pin did from the start of a run until the program stalled
+/// waiting for more data
+pub struct Waveform {
+    pub segments: Vec<Segment, MAX_SEGMENTS>,
+}
+
+impl Waveform {
+    fn record(&mut self, high: bool, cycles: u32) -> Result<(), Error> {
+        match self.segments.last_mut() {
+            Some(last) if last.high == high => last.cycles += cycles,
+            _ => self.segments.push(Segment { high, cycles }).map_err(|_| Error::TooLong)?,
+        }
+        Ok(())
+    }
+
+    /// Each complete high then low pulse, as (high, whole period) in
+    /// cycles. Anything before the first rising edge and the last pulse,
+    /// which is cut short by the stall, are left out.
+    pub fn pulses(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let start = self.segments.iter().position(|s| s.high).unwrap_or(self.segments.len());
+        self.segments[start..]
+            .windows(3)
+            .step_by(2)
+            .map(|w| (w[0].cycles, w[0].cycles + w[1].cycles))
+    }
+}
+
+struct StateMachine {
+    pc: u8,
+    x: u32,
+    y: u32,
+    osr: u32,
+    /// Bits shifted out of the OSR since it was last filled
+    shifted: u8,
+    pin: bool,
+}
+
+/// Run `program` from its first instruction with `fifo` queued, until it
+/// stalls on an empty FIFO. The OSR refills itself after `threshold` bits.
+pub fn run<const N: usize>(program: &Program<N>, fifo: &[u32], threshold: u8) -> Result<Waveform, Error> {
+    let mut waveform = Waveform { segments: Vec::new() };
+    let mut fifo = fifo.iter();
+    // The OSR starts empty, so the first `out` pulls
+    let mut sm = StateMachine { pc: 0, x: 0, y: 0, osr: 0, shifted: 32, pin: false };
+
+    let side_bits = program.side_set.bits();
+    let delay_bits = 5 - side_bits;
+
+    for _ in 0..MAX_SEGMENTS * 8 {
+        let instr = *program.code.get(sm.pc as usize).ok_or(Error::TooLong)?;
+        let field = (instr >> 8) & 0x1F;
+        let delay = (field & ((1 << delay_bits) - 1)) as u32;
+        let side = field >> delay_bits;
+        let side = if program.side_set.optional() {
+            (side >> (side_bits - 1) & 1 != 0).then_some(side & ((1 << (side_bits - 1)) - 1))
+        } else {
+            (side_bits > 0).then_some(side)
+        };
+        if let Some(side) = side {
+            sm.pin = side & 1 != 0;
+        }
+
+        let mut jump = None;
+        let arg = instr & 0xFF;
+        match instr >> 13 {
+            // jmp
+            0b000 => {
+                let taken = match arg >> 5 {
+                    0b000 => true,
+                    0b001 => sm.x == 0,
+                    0b010 => {
+                        let taken = sm.x != 0;
+                        sm.x = sm.x.wrapping_sub(1);
+                        taken
+                    }
+                    0b011 => sm.y == 0,
+                    0b100 => {
+                        let taken = sm.y != 0;
+                        sm.y = sm.y.wrapping_sub(1);
+                        taken
+                    }
+                    0b101 => sm.x != sm.y,
+                    0b111 => sm.shifted < threshold,
+                    _ => return Err(Error::Unsupported(instr)),
+                };
+                if taken {
+                    jump = Some((arg & 0x1F) as u8);
+                }
+            }
+            // out
+            0b011 => {
+                if sm.shifted >= threshold {
+                    let Some(&word) = fifo.next() else {
+                        return Ok(waveform);
+                    };
+                    sm.osr = word;
+                    sm.shifted = 0;
+                }
+                let count = match arg & 0x1F {
+                    0 => 32,
+                    n => n as u32,
+                };
+                let data = if count == 32 { sm.osr } else { sm.osr >> (32 - count) };
+                sm.osr = sm.osr.checked_shl(count).unwrap_or(0);
+                sm.shifted = (sm.shifted as u32 + count).min(32) as u8;
+                match arg >> 5 {
+                    0b000 => sm.pin = data & 1 != 0,
+                    0b001 => sm.x = data,
+                    0b010 => sm.y = data,
+                    0b011 => {}
+                    0b101 => jump = Some(data as u8),
+                    _ => return Err(Error::Unsupported(instr)),
+                }
+            }
+            // mov
+            0b101 => {
+                let value = match arg & 0b111 {
+                    0b001 => sm.x,
+                    0b010 => sm.y,
+                    0b011 => 0,
+                    _ => return Err(Error::Unsupported(instr)),
+                };
+                let value = match (arg >> 3) & 0b11 {
+                    0b00 => value,
+                    0b01 => !value,
+                    0b10 => value.reverse_bits(),
+                    _ => return Err(Error::Unsupported(instr)),
+                };
+                match arg >> 5 {
+                    0b001 => sm.x = value,
+                    0b010 => sm.y = value,
+                    _ => return Err(Error::Unsupported(instr)),
+                }
+            }
+            // set
+            0b111 => {
+                let data = (arg & 0x1F) as u32;
+                match arg >> 5 {
+                    0b000 => sm.pin = data & 1 != 0,
+                    0b001 => sm.x = data,
+                    0b010 => sm.y = data,
+                    _ => return Err(Error::Unsupported(instr)),
+                }
+            }
+            _ => return Err(Error::Unsupported(instr)),
+        }
+
+        waveform.record(sm.pin, 1 + delay)?;
+        sm.pc = match jump {
+            Some(target) => target,
+            None if sm.pc == program.wrap.source => program.wrap.target,
+            None => sm.pc + 1,
+        };
+    }
+    Err(Error::TooLong)
+}
@@ -1,184 +1,482 @@
+use core::fmt::Write as _;
+
+use crate::barcode;
+use crate::catalogue;
+use crate::clock::Timestamp;
+use crate::config::{self, BarcodeOutput};
+use crate::framebuffer::Framebuffer;
+use crate::layout;
+use crate::basket::{ReceiptGroup, MAX_LINES};
+use crate::color::Color;
+use crate::images::Images;
+use crate::led::{self, Layer, Pattern, Pixels};
+use crate::promotions::Discount;
+use crate::qr;
+use crate::state::Summary;
+use crate::tax::TaxBreakdown;
 use crate::UartWrap;
-use defmt::Format;
-use escpos_embed_image::embed_images;
+use defmt::warn;
 use escpos_embedded::{PrintSpeed, Printer};
+use embassy_futures::yield_now;
 use embassy_time::Duration;
 use embassy_time::Timer;
 use escpos_embedded::Image;
 use escpos_embed_image::embed_image;
+use heapless::{String, Vec};
 
 const FB_HEIGHT: usize = 238;
 
 const FRAMEBUFFER_SIZE: usize = (384 / 8) * FB_HEIGHT; // 384 pixels wide, FB_HEIGHT pixels tall, 1 bit per pixel
 
-embed_images!(
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Format)]
-    enum Images {
-        #[pattern("gfx/*.png")]
+// Where prices sit on item lines and on the footer
+const LINE_MARGIN: u16 = 10;
+const LINE_BASELINE: u16 = 61;
+const QUANTITY_RIGHT: u16 = 200;
+const TOTAL_MARGIN: u16 = 20;
+const TEXT_SPACING: u16 = 3;
+const TOTAL_BASELINE: u16 = 151;
+
+/// Runs along the key LEDs while printing
+const PRINTING: Pattern = Pattern::Chase {
+    color: Color::new(0, 0, 0, 137),
+    period: Duration::from_millis(800),
+    tail: 2,
+};
+/// The printer is still feeding paper for a while after it's taken the
+/// last of the data, so the chase carries on until this long after
+const PRINTING_LINGER: Duration = Duration::from_millis(1500);
+
+// Events
+pub enum DriverEvent {
+    /// When the transaction started, if the clock's set. The receipt number
+    /// isn't known until it's totalled, so it goes with the total instead.
+    PrintHeader { time: Option<Timestamp> },
+    PrintLine { image: Images, price: u16 },
+    /// Money taken off by a promotion
+    PrintDiscount { discount: Discount },
+    /// Running total for a transaction that is still open
+    PrintSubtotal { price: u16 },
+    PrintTotal { receipt: u32, price: u16, tax: TaxBreakdown, summary: Option<Summary>, time: Option<Timestamp> },
+    PrintVoid,
+    /// Void slip for a transaction that was cut short by a power loss
+    PrintInterrupted,
+    /// A whole receipt in one go, optionally marked as a copy. `time` is
+    /// when it was totalled, so a copy shows the same time as the original.
+    PrintReceipt {
+        receipt: u32,
+        time: Option<Timestamp>,
+        groups: Vec<ReceiptGroup, MAX_LINES>,
+        discounts: Vec<Discount, MAX_LINES>,
+        tax: TaxBreakdown,
+        summary: Option<Summary>,
+        copy: bool,
+    },
+    /// Confirms the time just set from the keypad
+    PrintTime { time: Timestamp },
+    /// End of day figures, plus the last receipt number ever issued
+    PrintZReport { receipts: u32, takings: u32, last_receipt: u32 },
+    /// Loyalty reward, with a code to make it look official
+    PrintCoupon { code: u32 },
+    /// Progress towards the next coupon
+    PrintStampCard { stamps: u32 },
+    /// Picture, price and barcode for the shelf edge, by catalogue id
+    PrintShelfLabel { product: u8 },
+}
+// Queue, deep enough for a full basket's lines plus its header, subtotal
+// and total, so scanning never waits on the paper
+pub static PRINT_EVENTS: embassy_sync::channel::Channel<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    DriverEvent,
+    { MAX_LINES + 4 },
+> = embassy_sync::channel::Channel::new();
+
+
+type Framebuf = Image<[u8; FRAMEBUFFER_SIZE]>;
+
+/// A write to the printer failed partway through a job. The UART has
+/// already raised the fault, so the rest of the job is dropped.
+struct Aborted;
+
+type Printed = Result<(), Aborted>;
+
+/// Stop the job at the first failed write, whatever error escpos wraps the UART's in
+trait OrAbort {
+    fn or_abort(self) -> Printed;
+}
+
+impl<E> OrAbort for Result<(), E> {
+    fn or_abort(self) -> Printed {
+        self.map_err(|_| Aborted)
+    }
+}
+
+/// Rows sent to the printer at a time. Writes to the UART block, so
+/// yielding between bands keeps the LED chase moving while a long image
+/// goes out.
+const BAND_ROWS: u16 = 24;
+
+/// Every image goes to the printer through here, a band at a time
+async fn print_image<T: AsRef<[u8]>>(printer: &mut Printer<UartWrap<'static>>, image: &Image<T>) -> Printed {
+    let stride = image.width.div_ceil(8) as usize;
+    let data = image.data.as_ref();
+    let mut top = 0;
+    while top < image.height {
+        let rows = BAND_ROWS.min(image.height - top);
+        let band = Image {
+            width: image.width,
+            height: rows,
+            data: &data[top as usize * stride..(top + rows) as usize * stride],
+        };
+        printer.print_image(&band).or_abort()?;
+        yield_now().await;
+        top += rows;
     }
-);
-
-const fn image_from_char(c: char) -> &'static Image<&'static [u8]> {
-    match c {
-        ' ' => &Images::Space.get_image(),
-        'x' => &Images::X.get_image(),
-        '£' => &Images::Pound.get_image(),
-        '1' => &Images::One.get_image(),
-        '2' => &Images::Two.get_image(),
-        '3' => &Images::Three.get_image(),
-        '4' => &Images::Four.get_image(),
-        '5' => &Images::Five.get_image(),
-        '6' => &Images::Six.get_image(),
-        '7' => &Images::Seven.get_image(),
-        '8' => &Images::Eight.get_image(),
-        '9' => &Images::Nine.get_image(),
-        '0' => &Images::Zero.get_image(),
-        _ => core::panic!("Unsupported character"), // Default to logo for unsupported characters
+    Ok(())
+}
+
+async fn print_header(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, receipt: Option<u32>, time: Option<Timestamp>) -> Printed {
+    print_image(printer, &Images::Header.get_image()).await?;
+    if let Some(receipt) = receipt {
+        print_receipt_number(printer, fb_image, receipt).await?;
     }
+    print_timestamp(printer, fb_image, time).await?;
+    printer.raw(&[0x0A]).or_abort()?;
+    Ok(())
 }
 
-fn images_from_price(price: u16) -> [&'static Image<&'static [u8]>; 5] {
-    let mut images = [Images::Space.get_image(); 5];
-    let mut remaining = price;
+async fn print_receipt_number(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, receipt: u32) -> Printed {
+    fb_image.clear();
+    let right = fb_image.width - LINE_MARGIN;
+    layout::draw_number(fb_image, receipt as i32, &layout::RECEIPT_NUMBER, right, LINE_BASELINE);
+    print_image(printer, &fb_image.head(80)).await?;
+    Ok(())
+}
 
-    for i in 0..5 {
-        if remaining == 0 && i < 5 {
-            images[i] = Images::Pound.get_image();
-            break;
-        } else {
-            let digit = remaining % 10;
-            images[i] = image_from_char(char::from_digit(digit as u32, 10).unwrap());
-            remaining /= 10;
+/// Date on one line and time on the next, or dashes if the clock isn't set
+async fn print_timestamp(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, time: Option<Timestamp>) -> Printed {
+    let mut date: String<16> = String::new();
+    let mut hours: String<16> = String::new();
+    match time {
+        Some(t) => {
+            let _ = write!(date, "{:02}/{:02}/{:02}", t.day, t.month, t.year % 100);
+            let _ = write!(hours, "{:02}:{:02}", t.hour, t.minute);
+        }
+        None => {
+            let _ = date.push_str("--/--/--");
+            let _ = hours.push_str("--:--");
         }
     }
 
-    images
+    fb_image.clear();
+    let right = fb_image.width - LINE_MARGIN;
+    layout::draw_text(fb_image, &date, TEXT_SPACING, right, LINE_BASELINE);
+    layout::draw_text(fb_image, &hours, TEXT_SPACING, right, LINE_BASELINE + 80);
+    print_image(printer, &fb_image.head(160)).await?;
+    Ok(())
 }
 
+async fn print_line(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, image: Images, quantity: u8, price: u16) -> Printed {
+    let produce_image = image.get_image();
+    fb_image.clear();
+    fb_image.blit_image(produce_image, 0, 0);
 
-trait Framebuffer {   
-    fn clear(&mut self);
-    fn blit_image<U: AsRef<[u8]>>(&mut self, src: &Image<U>, x_offset: u16, y_offset: u16);
+    if quantity > 1 {
+        layout::draw_number(fb_image, quantity as i32, &layout::QUANTITY, QUANTITY_RIGHT, LINE_BASELINE);
+    }
+    let right = fb_image.width - LINE_MARGIN;
+    layout::draw_number(fb_image, price as i32, &layout::PRICE, right, LINE_BASELINE);
+    print_image(printer, &fb_image.head(80)).await?;
+    Ok(())
+}
+
+async fn print_discount(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, discount: &Discount) -> Printed {
+    let deal_image = Images::Deal.get_image();
+    fb_image.clear();
+    fb_image.blit_image(discount.image.get_image(), 0, 0);
+    fb_image.blit_image(deal_image, QUANTITY_RIGHT - deal_image.width, 0);
 
-    fn head(&self, rows: u16) -> Image<&[u8]>;
+    let right = fb_image.width - LINE_MARGIN;
+    layout::draw_number(fb_image, -(discount.amount as i32), &layout::PRICE, right, LINE_BASELINE);
+    print_image(printer, &fb_image.head(80)).await?;
+    Ok(())
 }
 
-impl<const N: usize> Framebuffer for Image<[u8; N]> {
-    fn clear(&mut self) {
-        self.data.fill(0);
-    }
+/// A label on the left and an amount on the right
+async fn print_labelled(
+    printer: &mut Printer<UartWrap<'static>>,
+    fb_image: &mut Framebuf,
+    label: Images,
+    value: i32,
+    style: &layout::NumberStyle,
+) -> Printed {
+    fb_image.clear();
+    fb_image.blit_image(&label.get_image(), 0, 0);
+
+    let right = fb_image.width - LINE_MARGIN;
+    layout::draw_number(fb_image, value, style, right, LINE_BASELINE);
+    print_image(printer, &fb_image.head(80)).await?;
+    Ok(())
+}
 
-    fn head(&self, rows: u16) -> Image<&[u8]> {
-        Image {
-            width: self.width,
-            height: rows,
-            data: &self.data[..(rows as usize * self.width as usize / 8)],
-        }
+async fn print_subtotal(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, price: u16) -> Printed {
+    printer.feed(1).or_abort()?;
+    print_labelled(printer, fb_image, Images::Subtotal, price as i32, &layout::PRICE).await
+}
+
+async fn print_total(
+    printer: &mut Printer<UartWrap<'static>>,
+    fb_image: &mut Framebuf,
+    receipt: u32,
+    price: u16,
+    tax: &TaxBreakdown,
+    summary: Option<&str>,
+    time: Option<Timestamp>,
+) -> Printed {
+    printer.feed(1).or_abort()?;
+    if config::SHOW_VAT {
+        let (net, vat) = tax.in_pounds();
+        print_labelled(printer, fb_image, Images::Net, net as i32, &layout::PRICE).await?;
+        print_labelled(printer, fb_image, Images::Vat, vat as i32, &layout::PRICE).await?;
     }
+    fb_image.clear();
+    fb_image.blit_image(&Images::Footer.get_image(), 0, 0);
 
-    fn blit_image<U: AsRef<[u8]>>(&mut self, src: &Image<U>, x_offset: u16, y_offset: u16) {
-        let dest_stride = (self.width + 7) / 8;
-        let src_stride = (src.width + 7) / 8;
-        let src_data = src.data.as_ref();
+    let right = fb_image.width - TOTAL_MARGIN;
+    layout::draw_number(fb_image, price as i32, &layout::PRICE, right, TOTAL_BASELINE);
 
-        for y in 0..src.height {
-            let dy = y + y_offset;
-            if dy >= self.height {
-                continue;
-            }
+    print_image(printer, &*fb_image).await?;
+    if config::RECEIPT_BARCODE {
+        let mut number: String<10> = String::new();
+        let _ = write!(number, "{}", receipt);
+        print_barcode(printer, fb_image, Symbology::Code128, &number).await?;
+    }
+    print_timestamp(printer, fb_image, time).await?;
+    if let Some(text) = summary {
+        print_qr(printer, fb_image, text).await?;
+    }
+    Ok(())
+}
 
-            for x in 0..src.width {
-                let dx = x + x_offset;
-                if dx >= self.width {
-                    continue;
-                }
-
-                let src_byte = src_data[(y as usize) * (src_stride as usize) + (x / 8) as usize];
-                let src_bit = 7 - (x % 8);
-                let dest_idx = (dy as usize) * (dest_stride as usize) + (dx / 8) as usize;
-                let dest_bit = 7 - (dx % 8);
-                if ((src_byte >> src_bit) & 1) != 0 {
-                    self.data[dest_idx] |= 1 << dest_bit;
-                } else {
-                    self.data[dest_idx] &= !(1 << dest_bit);
-                }
-            }
+enum Symbology {
+    Code128,
+    Ean13,
+}
+
+/// A centred barcode, drawn by the printer or here depending on config
+async fn print_barcode(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, symbology: Symbology, text: &str) -> Printed {
+    match config::BARCODE_OUTPUT {
+        BarcodeOutput::Native => {
+            let mut command = [0u8; 64];
+            let len = match symbology {
+                Symbology::Code128 => barcode::code128_command(text, &mut command),
+                Symbology::Ean13 => barcode::ean13_command(text, &mut command),
+            };
+            let Some(len) = len else {
+                warn!("Can't make a barcode from {=str}", text);
+                return Ok(());
+            };
+            // Height, module width, digits underneath, then centred
+            printer.raw(&[0x1D, 0x68, config::BARCODE_HEIGHT as u8]).or_abort()?;
+            printer.raw(&[0x1D, 0x77, config::BARCODE_MODULE as u8]).or_abort()?;
+            printer.raw(&[0x1D, 0x48, 2]).or_abort()?;
+            printer.raw(&[0x1B, 0x61, 1]).or_abort()?;
+            printer.raw(&command[..len]).or_abort()?;
+            printer.raw(&[0x1B, 0x61, 0]).or_abort()?;
+        }
+        BarcodeOutput::Raster => {
+            let bars = match symbology {
+                Symbology::Code128 => barcode::code128(text),
+                Symbology::Ean13 => barcode::ean13(text),
+            };
+            let Some(bars) = bars else {
+                warn!("Can't make a barcode from {=str}", text);
+                return Ok(());
+            };
+            let module = config::BARCODE_MODULE.min(fb_image.width / bars.width()).max(1);
+            let height = config::BARCODE_HEIGHT.min(FB_HEIGHT as u16);
+            fb_image.clear();
+            bars.draw(fb_image, fb_image.width.saturating_sub(bars.width() * module) / 2, 0, module, height);
+            print_image(printer, &fb_image.head(height)).await?;
         }
     }
+    Ok(())
+}
 
+async fn print_qr(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, text: &str) -> Printed {
+    let Some(code) = qr::encode(text.as_bytes(), config::QR_EC_LEVEL) else {
+        warn!("Receipt summary is too long for a QR code");
+        return Ok(());
+    };
+    // Scale down if needed to leave a quiet zone of four modules all round
+    let size = code.size();
+    let module = config::QR_MODULE_SIZE
+        .min(fb_image.width / (size + 8))
+        .min(FB_HEIGHT as u16 / (size + 8))
+        .max(1);
+
+    fb_image.clear();
+    code.draw(fb_image, (fb_image.width - size * module) / 2, 4 * module, module);
+    print_image(printer, &fb_image.head((size + 8) * module)).await?;
+    Ok(())
 }
 
+async fn print_coupon(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, code: u32) -> Printed {
+    let coupon_image = Images::Coupon.get_image();
+    let width = fb_image.width;
+    fb_image.clear();
+    // Cut lines above and below a bordered ticket
+    fb_image.dashed_hline(0, 3, 12);
+    fb_image.draw_rect(LINE_MARGIN, 14, width - 2 * LINE_MARGIN, 210, 4);
+    fb_image.dashed_hline(FB_HEIGHT as u16 - 3, 3, 12);
+    fb_image.blit_image(coupon_image, (width - coupon_image.width) / 2, 34);
+
+    let mut text: String<16> = String::new();
+    let _ = write!(text, "{:03}-{:03}", code / 1000 % 1000, code % 1000);
+    let text_width = layout::text_width(&text, TEXT_SPACING);
+    layout::draw_text(fb_image, &text, TEXT_SPACING, (width + text_width) / 2, 170);
+    print_image(printer, &*fb_image).await?;
+    Ok(())
+}
 
-// Events
-pub enum DriverEvent {
-    PrintHeader,
-    PrintLine { image: Images, price: u16 },
-    PrintTotal { price: u16 },
-    PrintVoid,
+/// A row of boxes, one filled in for each stamp earned so far
+async fn print_stamp_card(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, stamps: u32) -> Printed {
+    let slots = config::LOYALTY_EVERY.max(1) as u16;
+    let slot = (fb_image.width - 2 * LINE_MARGIN) / slots;
+    let size = slot.saturating_sub(6).clamp(1, 60);
+    fb_image.clear();
+    for i in 0..slots {
+        let x = LINE_MARGIN + i * slot + (slot - size) / 2;
+        if (i as u32) < stamps {
+            fb_image.fill_rect(x, 2, size, size);
+        } else {
+            fb_image.draw_rect(x, 2, size, size, 3);
+        }
+    }
+    print_image(printer, &fb_image.head(size + 4)).await?;
+    Ok(())
 }
-// Queue
-pub static PRINT_EVENTS: embassy_sync::channel::Channel<
-    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-    DriverEvent,
-    1,
-> = embassy_sync::channel::Channel::new();
 
+/// One job off the queue, stopping at the first write that fails
+async fn print_event(printer: &mut Printer<UartWrap<'static>>, fb_image: &mut Framebuf, event: DriverEvent) -> Printed {
+    match event {
+        DriverEvent::PrintHeader { time } => {
+            print_header(printer, fb_image, None, time).await?;
+        }
+        DriverEvent::PrintLine { image, price } => {
+            print_line(printer, fb_image, image, 1, price).await?;
+        }
+        DriverEvent::PrintDiscount { discount } => {
+            print_discount(printer, fb_image, &discount).await?;
+        }
+        DriverEvent::PrintSubtotal { price } => {
+            print_subtotal(printer, fb_image, price).await?;
+            printer.raw(&[0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintTotal { receipt, price, tax, summary, time } => {
+            print_receipt_number(printer, fb_image, receipt).await?;
+            print_total(printer, fb_image, receipt, price, &tax, summary.as_deref(), time).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintVoid => {
+            printer.raw(&[0x0A]).or_abort()?;
+            print_image(printer, &Images::Void.get_image()).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintInterrupted => {
+            printer.raw(&[0x0A]).or_abort()?;
+            print_image(printer, &Images::Void.get_image()).await?;
+            print_image(printer, &Images::Interrupted.get_image()).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintReceipt { receipt, time, groups, discounts, tax, summary, copy } => {
+            print_header(printer, fb_image, Some(receipt), time).await?;
+            if copy {
+                print_image(printer, &Images::Copy.get_image()).await?;
+            }
+            for group in groups.iter() {
+                print_line(printer, fb_image, group.image, group.quantity, group.price()).await?;
+            }
+            let subtotal: u16 = groups.iter().map(|group| group.price()).sum();
+            print_subtotal(printer, fb_image, subtotal).await?;
+            for discount in discounts.iter() {
+                print_discount(printer, fb_image, discount).await?;
+            }
+            let total = subtotal - discounts.iter().map(|discount| discount.amount).sum::<u16>();
+            print_total(printer, fb_image, receipt, total, &tax, summary.as_deref(), time).await?;
+            if copy {
+                print_image(printer, &Images::Copy.get_image()).await?;
+            }
+            printer.raw(&[0x0A, 0x0A, 0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintTime { time } => {
+            print_timestamp(printer, fb_image, Some(time)).await?;
+        }
+        DriverEvent::PrintZReport { receipts, takings, last_receipt } => {
+            print_image(printer, &Images::Zreport.get_image()).await?;
+
+            fb_image.clear();
+            let right = fb_image.width - LINE_MARGIN;
+            layout::draw_number(fb_image, last_receipt as i32, &layout::RECEIPT_NUMBER, right, LINE_BASELINE);
+            print_image(printer, &fb_image.head(80)).await?;
+
+            fb_image.clear();
+            layout::draw_number(fb_image, receipts as i32, &layout::QUANTITY, right, LINE_BASELINE);
+            print_image(printer, &fb_image.head(80)).await?;
+
+            fb_image.clear();
+            layout::draw_number(fb_image, takings as i32, &layout::PRICE, right, LINE_BASELINE);
+            print_image(printer, &fb_image.head(80)).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintCoupon { code } => {
+            print_coupon(printer, fb_image, code).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintStampCard { stamps } => {
+            print_stamp_card(printer, fb_image, stamps).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+        DriverEvent::PrintShelfLabel { product } => {
+            let Some(product) = catalogue::product(product) else {
+                warn!("No product {} for a shelf label", product);
+                return Ok(());
+            };
+            print_line(printer, fb_image, product.image, 1, product.price).await?;
+            print_barcode(printer, fb_image, Symbology::Ean13, product.barcode).await?;
+            printer.raw(&[0x0A, 0x0A, 0x0A]).or_abort()?;
+        }
+    }
+    Ok(())
+}
+
+fn setup(printer: &mut Printer<UartWrap<'static>>) -> Printed {
+    printer.set_software_flow_control(false).or_abort()?;
+    printer.set_max_speed(200).or_abort()?;
+    printer.set_print_speed(PrintSpeed::Speed3).or_abort()?;
+    Ok(())
+}
 
 pub async fn driver(mut printer: Printer<UartWrap<'static>>) {
 
-    let mut fb_image: Image<[u8; FRAMEBUFFER_SIZE]> = Image {
+    let mut fb_image: Framebuf = Image {
         width: 384,
         height: FB_HEIGHT as u16,
         data: [0u8; FRAMEBUFFER_SIZE],
     };
 
-    printer.set_software_flow_control(false).unwrap();
-    printer.set_max_speed(200).unwrap();
-    printer.set_print_speed(PrintSpeed::Speed3).unwrap();
+    if setup(&mut printer).is_err() {
+        warn!("Couldn't configure the printer, carrying on with its defaults");
+    }
 
     Timer::after(Duration::from_millis(200)).await;
 
 
     // Main loop here
     loop {
-        match PRINT_EVENTS.receive().await {            
-            DriverEvent::PrintHeader => {
-                printer.print_image(&Images::Header.get_image()).unwrap();
-                printer.raw(&[0x0A]).unwrap();
-            }
-            DriverEvent::PrintLine { image, price } => {
-                let produce_image = image.get_image();
-                fb_image.clear();
-                fb_image.blit_image(produce_image, 0, 0);
-
-                let mut cur_x = fb_image.width as u16 - 10;
-                let price_images = images_from_price(price);
-                for img in price_images.iter() {
-                    fb_image.blit_image(img, cur_x - img.width, 0);
-                    cur_x -= img.width + 5;
-                }
-                printer.print_image(&fb_image.head(80)).unwrap();
-            }
-            DriverEvent::PrintTotal { price } => {
-                printer.feed(1).unwrap();
-                fb_image.clear();
-                fb_image.blit_image(&Images::Footer.get_image(), 0, 0);
-
-                let mut cur_x = fb_image.width - 20;
-                let price_images = images_from_price(price);
-                for img in price_images.iter() {
-                    fb_image.blit_image(img, cur_x - img.width, 90);
-                    cur_x -= img.width + 5;
-                }
-
-                printer.print_image(&fb_image).unwrap();
-                printer.raw(&[0x0A, 0x0A, 0x0A, 0x0A, 0x0A]).unwrap();
-            }
-            DriverEvent::PrintVoid => {
-                printer.raw(&[0x0A]).unwrap();
-                printer.print_image(&Images::Void.get_image()).unwrap();
-                printer.raw(&[0x0A, 0x0A, 0x0A]).unwrap();
-            }
+        let event = PRINT_EVENTS.receive().await;
+        led::set(Layer::Base, Pixels::KEYS, PRINTING, Some(PRINTING_LINGER));
+        if print_event(&mut printer, &mut fb_image, event).await.is_err() {
+            warn!("Print job abandoned after a UART error");
         }
     }
 
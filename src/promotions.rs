@@ -0,0 +1,176 @@
+//! Pricing rules applied to a basket when it's totalled.
+//!
+//! The rules live in `config::PROMOTIONS` and are tried in order, each one
+//! using up the items it applies to so that no item is discounted twice.
+
+use defmt::Format;
+use heapless::Vec;
+
+use crate::basket::{group_lines, LineItem, MAX_LINES};
+use crate::config;
+use crate::images::Images;
+
+pub enum Promotion {
+    /// `quantity` of a product for `price`, e.g. 3 bananas for £5
+    MultiBuy { product: Images, quantity: u8, price: u16 },
+    /// Every second one of a product is free
+    BuyOneGetOneFree { product: Images },
+    /// One each of several different products for `price`
+    MealDeal { products: &'static [Images], price: u16 },
+}
+
+/// Money off a basket, printed against the image of the product it's for
+#[derive(Clone, Copy, Format)]
+pub struct Discount {
+    pub image: Images,
+    pub amount: u16,
+    /// Everything the offer was for. VAT takes the discount off each of
+    /// these in proportion to its price.
+    pub products: &'static [Images],
+}
+
+pub fn discounts(lines: &[LineItem]) -> Vec<Discount, MAX_LINES> {
+    apply(config::PROMOTIONS, lines)
+}
+
+fn apply(promotions: &'static [Promotion], lines: &[LineItem]) -> Vec<Discount, MAX_LINES> {
+    // Items not yet used by a promotion
+    let mut groups = group_lines(lines);
+    let mut discounts = Vec::new();
+
+    for promotion in promotions {
+        let discount = match *promotion {
+            Promotion::MultiBuy { ref product, quantity, price } => {
+                let Some(group) = groups.iter_mut().find(|g| g.image == *product) else {
+                    continue;
+                };
+                let sets = group.quantity / quantity.max(1);
+                group.quantity -= sets * quantity;
+                let saving = (group.unit_price * quantity as u16).saturating_sub(price);
+                Discount { image: *product, amount: saving * sets as u16, products: core::slice::from_ref(product) }
+            }
+            Promotion::BuyOneGetOneFree { ref product } => {
+                let Some(group) = groups.iter_mut().find(|g| g.image == *product) else {
+                    continue;
+                };
+                let pairs = group.quantity / 2;
+                group.quantity -= pairs * 2;
+                Discount { image: *product, amount: group.unit_price * pairs as u16, products: core::slice::from_ref(product) }
+            }
+            Promotion::MealDeal { products, price } => {
+                let mut deals = u8::MAX;
+                let mut full_price = 0;
+                for product in products {
+                    let group = groups.iter().find(|g| g.image == *product);
+                    deals = deals.min(group.map_or(0, |g| g.quantity));
+                    full_price += group.map_or(0, |g| g.unit_price);
+                }
+                if deals == 0 || products.is_empty() {
+                    continue;
+                }
+                for group in groups.iter_mut().filter(|g| products.contains(&g.image)) {
+                    group.quantity -= deals;
+                }
+                let saving = full_price.saturating_sub(price);
+                Discount { image: products[0], amount: saving * deals as u16, products }
+            }
+        };
+        if discount.amount > 0 {
+            // Only fails if there are more rules than MAX_LINES
+            let _ = discounts.push(discount);
+        }
+    }
+    discounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basket::InputEvent;
+    use crate::catalogue;
+    use crate::scanner;
+
+    fn press(image: Images) -> InputEvent {
+        let product = catalogue::PRODUCTS.iter().find(|p| p.image == image).unwrap();
+        InputEvent::ProduceButtonPressed { image, price: product.price }
+    }
+
+    /// Ring up a basket the way the till does: items are added as they're
+    /// pressed or scanned, and void starts again
+    fn ring_up(events: impl IntoIterator<Item = InputEvent>) -> std::vec::Vec<LineItem> {
+        let mut lines = std::vec::Vec::new();
+        for event in events {
+            match event {
+                InputEvent::ProduceButtonPressed { image, price } => lines.push(LineItem { image, price }),
+                InputEvent::VoidButtonPressed | InputEvent::VoidButtonLongPressed => lines.clear(),
+                _ => {}
+            }
+        }
+        lines
+    }
+
+    fn amounts(discounts: &[Discount]) -> std::vec::Vec<(Images, u16)> {
+        discounts.iter().map(|d| (d.image, d.amount)).collect()
+    }
+
+    #[test]
+    fn multi_buy_leaves_the_rest_at_full_price() {
+        // Three for £5 on £2 bananas, so two sets save £2 and the seventh
+        // is full price
+        let lines = ring_up([Images::Banana; 7].map(press));
+        assert_eq!(amounts(&discounts(&lines)), [(Images::Banana, 2)]);
+
+        let lines = ring_up([Images::Banana; 2].map(press));
+        assert!(discounts(&lines).is_empty());
+    }
+
+    #[test]
+    fn buy_one_get_one_free_with_an_odd_count() {
+        let lines = ring_up([Images::Pie; 3].map(press));
+        assert_eq!(amounts(&discounts(&lines)), [(Images::Pie, 8)]);
+    }
+
+    #[test]
+    fn meal_deal_needs_every_item() {
+        let lines = ring_up([press(Images::Bread), press(Images::Cheese), press(Images::Bread)]);
+        assert!(discounts(&lines).is_empty());
+
+        // Scanning the juice completes one deal
+        let lines = ring_up([
+            press(Images::Bread),
+            press(Images::Cheese),
+            press(Images::Bread),
+            scanner::lookup(b"2000000000022"),
+        ]);
+        let found = discounts(&lines);
+        assert_eq!(amounts(&found), [(Images::Bread, 2)]);
+        assert_eq!(found[0].products, [Images::Bread, Images::Cheese, Images::Juice]);
+    }
+
+    #[test]
+    fn void_starts_the_basket_again() {
+        let lines = ring_up([
+            press(Images::Pie),
+            press(Images::Pie),
+            InputEvent::VoidButtonPressed,
+            press(Images::Pie),
+            InputEvent::UnknownBarcode,
+        ]);
+        assert!(discounts(&lines).is_empty());
+    }
+
+    #[test]
+    fn an_item_is_only_used_by_one_rule() {
+        const RULES: &[Promotion] = &[
+            Promotion::MultiBuy { product: Images::Juice, quantity: 2, price: 1 },
+            Promotion::MealDeal { products: &[Images::Bread, Images::Juice], price: 5 },
+        ];
+        // The multi-buy comes first and takes both juices
+        let lines = ring_up([press(Images::Juice), press(Images::Bread), press(Images::Juice)]);
+        assert_eq!(amounts(&apply(RULES, &lines)), [(Images::Juice, 1)]);
+
+        // A third juice is left for the meal deal
+        let lines = ring_up([Images::Juice, Images::Bread, Images::Juice, Images::Juice].map(press));
+        assert_eq!(amounts(&apply(RULES, &lines)), [(Images::Juice, 1), (Images::Bread, 1)]);
+    }
+}
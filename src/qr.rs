@@ -0,0 +1,598 @@
+//! QR code encoder, for drawing codes onto receipts.
+//!
+//! Only byte mode is supported, at versions 1 to `MAX_VERSION`, which is
+//! plenty for a receipt summary and keeps the code small enough to print
+//! at a readable size. The construction follows ISO/IEC 18004.
+
+use crate::framebuffer::Framebuffer;
+
+/// Largest version (and so the most data) that `encode` will produce
+pub const MAX_VERSION: u8 = 10;
+
+const MAX_SIZE: usize = 17 + 4 * MAX_VERSION as usize;
+const GRID_BYTES: usize = (MAX_SIZE * MAX_SIZE).div_ceil(8);
+/// Data and error correction codewords in a version `MAX_VERSION` code
+const MAX_CODEWORDS: usize = 346;
+const MAX_ECC_PER_BLOCK: usize = 30;
+
+/// How much of the code can be damaged and still be read
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EcLevel {
+    /// About 7%
+    L,
+    /// About 15%
+    M,
+    /// About 25%
+    Q,
+    /// About 30%
+    H,
+}
+
+impl EcLevel {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn format_bits(self) -> u32 {
+        match self {
+            EcLevel::L => 1,
+            EcLevel::M => 0,
+            EcLevel::Q => 3,
+            EcLevel::H => 2,
+        }
+    }
+}
+
+// Indexed by error correction level then version - 1
+const ECC_CODEWORDS_PER_BLOCK: [[u8; MAX_VERSION as usize]; 4] = [
+    [7, 10, 15, 20, 26, 18, 20, 24, 30, 18],
+    [10, 16, 26, 18, 24, 16, 18, 22, 22, 26],
+    [13, 22, 18, 26, 18, 24, 18, 22, 20, 24],
+    [17, 28, 22, 16, 22, 28, 26, 26, 24, 28],
+];
+const ECC_BLOCKS: [[u8; MAX_VERSION as usize]; 4] = [
+    [1, 1, 1, 1, 1, 2, 2, 2, 2, 4],
+    [1, 1, 1, 2, 2, 4, 4, 4, 5, 5],
+    [1, 1, 2, 2, 4, 4, 6, 6, 8, 8],
+    [1, 1, 2, 4, 4, 4, 5, 6, 8, 8],
+];
+
+/// A square of modules, one bit each
+#[derive(Clone)]
+struct Grid {
+    size: usize,
+    bits: [u8; GRID_BYTES],
+}
+
+impl Grid {
+    fn new(size: usize) -> Self {
+        Self { size, bits: [0; GRID_BYTES] }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        let i = y * self.size + x;
+        self.bits[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        let i = y * self.size + x;
+        if dark {
+            self.bits[i / 8] |= 0x80 >> (i % 8);
+        } else {
+            self.bits[i / 8] &= !(0x80 >> (i % 8));
+        }
+    }
+}
+
+/// An encoded QR code
+pub struct QrCode {
+    modules: Grid,
+}
+
+/// Appends bits to a codeword buffer, most significant first
+struct BitWriter {
+    bytes: [u8; MAX_CODEWORDS],
+    len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, bits: u8) {
+        for i in (0..bits).rev() {
+            if value >> i & 1 != 0 {
+                self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Encode `data` in byte mode, using the smallest version it fits in.
+///
+/// Returns None if the data is too long for `MAX_VERSION`.
+pub fn encode(data: &[u8], ec: EcLevel) -> Option<QrCode> {
+    let version = (1..=MAX_VERSION).find(|&v| header_bits(v) + data.len() * 8 <= data_codewords(v, ec) * 8)?;
+    let capacity = data_codewords(version, ec);
+
+    let mut writer = BitWriter { bytes: [0; MAX_CODEWORDS], len: 0 };
+    writer.push(0b0100, 4);
+    writer.push(data.len() as u32, count_bits(version));
+    for &byte in data {
+        writer.push(byte as u32, 8);
+    }
+    // Terminator, then pad to a whole byte and fill with the pad codewords
+    let terminator = (capacity * 8 - writer.len).min(4);
+    writer.push(0, terminator as u8);
+    writer.push(0, ((8 - writer.len % 8) % 8) as u8);
+    for pad in [0xEC, 0x11].iter().cycle().take(capacity - writer.len / 8) {
+        writer.push(*pad, 8);
+    }
+
+    let codewords = add_ecc_and_interleave(&writer.bytes[..capacity], version, ec);
+
+    let size = 17 + 4 * version as usize;
+    let mut modules = Grid::new(size);
+    let mut function = Grid::new(size);
+    draw_function_patterns(&mut modules, &mut function, version, ec);
+    draw_codewords(&mut modules, &function, &codewords[..raw_codewords(version)]);
+
+    // Pick the mask that leaves the fewest confusing features
+    let mut best: Option<(u32, Grid)> = None;
+    for mask in 0..8 {
+        let mut masked = modules.clone();
+        apply_mask(&mut masked, &function, mask);
+        draw_format_bits(&mut masked, &mut function, ec, mask);
+        let score = penalty(&masked);
+        if best.as_ref().is_none_or(|(best_score, _)| score < *best_score) {
+            best = Some((score, masked));
+        }
+    }
+    let (_, modules) = best?;
+    Some(QrCode { modules })
+}
+
+impl QrCode {
+    /// Width and height in modules, not including the quiet zone
+    pub fn size(&self) -> u16 {
+        self.modules.size as u16
+    }
+
+    pub fn module(&self, x: u16, y: u16) -> bool {
+        self.modules.get(x as usize, y as usize)
+    }
+
+    /// Draw the code with its top left corner at (`x`, `y`), each module
+    /// `module_size` pixels square. Leaving a light border of four modules
+    /// around it is up to the caller.
+    pub fn draw<F: Framebuffer>(&self, fb: &mut F, x: u16, y: u16, module_size: u16) {
+        let size = self.size();
+        for my in 0..size {
+            for mx in 0..size {
+                if self.module(mx, my) {
+                    fb.fill_rect(x + mx * module_size, y + my * module_size, module_size, module_size);
+                }
+            }
+        }
+    }
+}
+
+fn count_bits(version: u8) -> u8 {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+fn header_bits(version: u8) -> usize {
+    4 + count_bits(version) as usize
+}
+
+/// Modules available for codewords once the function patterns are drawn
+fn raw_data_modules(version: u8) -> usize {
+    let v = version as usize;
+    let mut result = (16 * v + 128) * v + 64;
+    if v >= 2 {
+        let aligns = v / 7 + 2;
+        result -= (25 * aligns - 10) * aligns - 55;
+        if v >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn raw_codewords(version: u8) -> usize {
+    raw_data_modules(version) / 8
+}
+
+fn data_codewords(version: u8, ec: EcLevel) -> usize {
+    let v = version as usize - 1;
+    raw_codewords(version)
+        - ECC_CODEWORDS_PER_BLOCK[ec.index()][v] as usize * ECC_BLOCKS[ec.index()][v] as usize
+}
+
+/// Split the data into blocks, add error correction to each, and interleave
+/// them in the order they're placed in the symbol
+fn add_ecc_and_interleave(data: &[u8], version: u8, ec: EcLevel) -> [u8; MAX_CODEWORDS] {
+    let v = version as usize - 1;
+    let blocks = ECC_BLOCKS[ec.index()][v] as usize;
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[ec.index()][v] as usize;
+    let raw = raw_codewords(version);
+    // The first few blocks are one data codeword shorter than the rest
+    let short_blocks = blocks - raw % blocks;
+    let short_data_len = raw / blocks - ecc_len;
+    let block_start = |i: usize| i * short_data_len + i.saturating_sub(short_blocks);
+    let block_len = |i: usize| short_data_len + (i >= short_blocks) as usize;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut ecc = [0u8; MAX_CODEWORDS];
+    for i in 0..blocks {
+        let start = block_start(i);
+        let remainder = reed_solomon_remainder(&data[start..start + block_len(i)], &divisor[..ecc_len]);
+        ecc[i * ecc_len..(i + 1) * ecc_len].copy_from_slice(&remainder[..ecc_len]);
+    }
+
+    let mut result = [0u8; MAX_CODEWORDS];
+    let mut n = 0;
+    for j in 0..=short_data_len {
+        for i in 0..blocks {
+            if j < block_len(i) {
+                result[n] = data[block_start(i) + j];
+                n += 1;
+            }
+        }
+    }
+    for j in 0..ecc_len {
+        for i in 0..blocks {
+            result[n] = ecc[i * ecc_len + j];
+            n += 1;
+        }
+    }
+    result
+}
+
+/// Multiply in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+    z as u8
+}
+
+fn reed_solomon_divisor(degree: usize) -> [u8; MAX_ECC_PER_BLOCK] {
+    let mut result = [0u8; MAX_ECC_PER_BLOCK];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> [u8; MAX_ECC_PER_BLOCK] {
+    let degree = divisor.len();
+    let mut result = [0u8; MAX_ECC_PER_BLOCK];
+    for &byte in data {
+        let factor = byte ^ result[0];
+        result.copy_within(1..degree, 0);
+        result[degree - 1] = 0;
+        for (r, &d) in result[..degree].iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+fn draw_function_patterns(modules: &mut Grid, function: &mut Grid, version: u8, ec: EcLevel) {
+    let size = modules.size;
+    let mut set = |x: usize, y: usize, dark: bool| {
+        modules.set(x, y, dark);
+        function.set(x, y, true);
+    };
+
+    // Timing patterns
+    for i in 0..size {
+        set(6, i, i % 2 == 0);
+        set(i, 6, i % 2 == 0);
+    }
+
+    // Finder patterns and their separators
+    for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                    let distance = dx.abs().max(dy.abs());
+                    set(x as usize, y as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    // Alignment patterns, except where they'd overlap the finders
+    let (positions, count) = alignment_positions(version);
+    for i in 0..count {
+        for j in 0..count {
+            if (i == 0 && (j == 0 || j == count - 1)) || (i == count - 1 && j == 0) {
+                continue;
+            }
+            for dy in -2i32..=2 {
+                for dx in -2i32..=2 {
+                    let x = (positions[i] as i32 + dx) as usize;
+                    let y = (positions[j] as i32 + dy) as usize;
+                    set(x, y, dx.abs().max(dy.abs()) != 1);
+                }
+            }
+        }
+    }
+
+    // Version information
+    if version >= 7 {
+        let mut remainder = version as u32;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+        let bits = (version as u32) << 12 | remainder;
+        for i in 0..18 {
+            let dark = bits >> i & 1 != 0;
+            let a = size - 11 + i % 3;
+            let b = i / 3;
+            set(a, b, dark);
+            set(b, a, dark);
+        }
+    }
+
+    // Reserve the format information areas, filled in once the mask is known
+    draw_format_bits(modules, function, ec, 0);
+}
+
+/// Centre coordinates of the alignment patterns, on both axes
+fn alignment_positions(version: u8) -> ([u8; 7], usize) {
+    let mut positions = [0u8; 7];
+    if version == 1 {
+        return (positions, 0);
+    }
+    let v = version as usize;
+    let count = v / 7 + 2;
+    let step = (v * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut pos = 17 + 4 * v - 7;
+    for i in (1..count).rev() {
+        positions[i] = pos as u8;
+        pos -= step;
+    }
+    positions[0] = 6;
+    (positions, count)
+}
+
+fn draw_format_bits(modules: &mut Grid, function: &mut Grid, ec: EcLevel, mask: u8) {
+    let size = modules.size;
+    let data = ec.format_bits() << 3 | mask as u32;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    let bits = (data << 10 | remainder) ^ 0x5412;
+    let bit = |i: usize| bits >> i & 1 != 0;
+    let mut set = |x: usize, y: usize, dark: bool| {
+        modules.set(x, y, dark);
+        function.set(x, y, true);
+    };
+
+    // Around the top left finder
+    for i in 0..=5 {
+        set(8, i, bit(i));
+    }
+    set(8, 7, bit(6));
+    set(8, 8, bit(7));
+    set(7, 8, bit(8));
+    for i in 9..15 {
+        set(14 - i, 8, bit(i));
+    }
+
+    // Split between the other two finders
+    for i in 0..8 {
+        set(size - 1 - i, 8, bit(i));
+    }
+    for i in 8..15 {
+        set(8, size - 15 + i, bit(i));
+    }
+    // Always dark
+    set(8, size - 8, true);
+}
+
+/// Place codewords in the zigzag order, two columns at a time from the right
+fn draw_codewords(modules: &mut Grid, function: &Grid, codewords: &[u8]) {
+    let size = modules.size;
+    let total_bits = codewords.len() * 8;
+    let mut i = 0;
+    let mut right = size - 1;
+    loop {
+        // Skip the vertical timing pattern
+        if right == 6 {
+            right = 5;
+        }
+        let upward = (right + 1) & 2 == 0;
+        for vert in 0..size {
+            for x in [right, right - 1] {
+                let y = if upward { size - 1 - vert } else { vert };
+                if !function.get(x, y) && i < total_bits {
+                    modules.set(x, y, codewords[i / 8] & (0x80 >> (i % 8)) != 0);
+                    i += 1;
+                }
+            }
+        }
+        if right < 2 {
+            break;
+        }
+        right -= 2;
+    }
+}
+
+fn apply_mask(modules: &mut Grid, function: &Grid, mask: u8) {
+    let size = modules.size;
+    for y in 0..size {
+        for x in 0..size {
+            let invert = match mask {
+                0 => (x + y) % 2 == 0,
+                1 => y % 2 == 0,
+                2 => x % 3 == 0,
+                3 => (x + y) % 3 == 0,
+                4 => (x / 3 + y / 2) % 2 == 0,
+                5 => x * y % 2 + x * y % 3 == 0,
+                6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+            };
+            if invert && !function.get(x, y) {
+                modules.set(x, y, !modules.get(x, y));
+            }
+        }
+    }
+}
+
+/// Score how hard a masked symbol might be to scan, lower is better
+fn penalty(modules: &Grid) -> u32 {
+    let size = modules.size;
+    let mut score = 0;
+
+    for i in 0..size {
+        score += line_penalty(size, |j| modules.get(j, i));
+        score += line_penalty(size, |j| modules.get(i, j));
+    }
+
+    // 2x2 blocks of one colour
+    for y in 0..size - 1 {
+        for x in 0..size - 1 {
+            let colour = modules.get(x, y);
+            if colour == modules.get(x + 1, y) && colour == modules.get(x, y + 1) && colour == modules.get(x + 1, y + 1) {
+                score += 3;
+            }
+        }
+    }
+
+    // Balance of dark and light, 10 points for every 5% away from half
+    let total = (size * size) as i32;
+    let dark = (0..size)
+        .flat_map(|y| (0..size).map(move |x| (x, y)))
+        .filter(|&(x, y)| modules.get(x, y))
+        .count() as i32;
+    let k = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
+    score += k as u32 * 10;
+    score
+}
+
+/// Runs of five or more, and patterns that look like a finder
+fn line_penalty(size: usize, get: impl Fn(usize) -> bool) -> u32 {
+    let mut score = 0;
+
+    let mut run = 0;
+    let mut colour = false;
+    for i in 0..size {
+        if i > 0 && get(i) == colour {
+            run += 1;
+        } else {
+            if run >= 5 {
+                score += 3 + (run - 5);
+            }
+            colour = get(i);
+            run = 1;
+        }
+    }
+    if run >= 5 {
+        score += 3 + (run - 5);
+    }
+
+    // Dark-light-dark-dark-dark-light-dark with four light on either side,
+    // treating anything outside the symbol as light
+    const FINDER: [bool; 7] = [true, false, true, true, true, false, true];
+    let light = |i: isize| i < 0 || i >= size as isize || !get(i as usize);
+    for start in 0..size.saturating_sub(6) {
+        if (0..7).all(|j| get(start + j) == FINDER[j]) {
+            let s = start as isize;
+            if (1..=4).all(|j| light(s - j)) || (0..4).all(|j| light(s + 7 + j)) {
+                score += 40;
+            }
+        }
+    }
+    score as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Laid out like the summary `state` puts under the total
+    const SUMMARY: &str = "Receipt #1042\nBanana x3 £6\nJuice x1 £1\nEggs x2 £6\nBanana deal -£1\nTotal £12";
+
+    /// The summary, repeated or cut short to exactly `len` bytes
+    fn summary(len: usize) -> Vec<u8> {
+        SUMMARY.bytes().cycle().take(len).collect()
+    }
+
+    /// Most bytes a version can hold at a level
+    fn capacity(version: u8, ec: EcLevel) -> usize {
+        (data_codewords(version, ec) * 8 - header_bits(version)) / 8
+    }
+
+    /// Read a code back with rqrr, as a bitmap with a four module quiet zone
+    /// and each module three pixels square
+    fn decode(code: &QrCode) -> (rqrr::MetaData, Vec<u8>) {
+        const SCALE: usize = 3;
+        const QUIET: usize = 4;
+        let size = code.size() as usize;
+        let width = (size + 2 * QUIET) * SCALE;
+        let mut image = rqrr::PreparedImage::prepare_from_bitmap(width, width, |x, y| {
+            let (mx, my) = (x / SCALE, y / SCALE);
+            let inside = |m: usize| (QUIET..QUIET + size).contains(&m);
+            inside(mx) && inside(my) && code.module((mx - QUIET) as u16, (my - QUIET) as u16)
+        });
+        let grids = image.detect_grids();
+        assert_eq!(grids.len(), 1, "expected one code in the image");
+        let mut data = Vec::new();
+        let meta = grids[0].decode_to(&mut data).expect("code should decode");
+        (meta, data)
+    }
+
+    #[test]
+    fn decodes_at_every_level_and_version() {
+        for ec in [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H] {
+            for version in [1, 7, 10] {
+                // Filling the version exactly means a smaller one can't be used
+                let data = summary(capacity(version, ec));
+                let code = encode(&data, ec).unwrap();
+                assert_eq!(code.size(), 17 + 4 * version as u16);
+
+                let (meta, decoded) = decode(&code);
+                assert_eq!(meta.version.0, version as usize);
+                assert_eq!(meta.ecc_level, ec.format_bits() as u16);
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn one_byte_over_goes_up_a_version() {
+        for ec in [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H] {
+            for version in [1, 7] {
+                let data = summary(capacity(version, ec) + 1);
+                let code = encode(&data, ec).unwrap();
+                assert_eq!(code.size(), 17 + 4 * (version as u16 + 1));
+                assert_eq!(decode(&code).1, data);
+            }
+        }
+    }
+
+    #[test]
+    fn too_long_for_max_version() {
+        for ec in [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H] {
+            assert!(encode(&summary(capacity(MAX_VERSION, ec) + 1), ec).is_none());
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Serial barcode scanner on UART0.
+//!
+//! Scanners send each code as a line of text. Codes for products in the
+//! catalogue ring them up just like their key, anything else gets the error
+//! flash.
+
+use heapless::Vec;
+
+use crate::basket::InputEvent;
+use crate::catalogue;
+
+// Only reading the UART needs the hardware
+#[cfg(not(test))]
+use {
+    crate::state::INPUT_EVENTS,
+    defmt::{info, warn},
+    embassy_rp::uart::BufferedUartRx,
+    embedded_io_async::Read,
+};
+
+/// Longest code we'd expect from a scanner
+const MAX_SCAN: usize = 32;
+
+/// Assembles bytes from the scanner into lines
+pub struct LineBuffer {
+    line: Vec<u8, MAX_SCAN>,
+    too_long: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self { line: Vec::new(), too_long: false }
+    }
+
+    /// Add a byte, returning the scan once its line ends. Blank lines and
+    /// lines too long to be a barcode are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8, MAX_SCAN>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let too_long = core::mem::take(&mut self.too_long);
+                (!too_long && !line.is_empty()).then_some(line)
+            }
+            _ => {
+                if self.line.push(byte).is_err() {
+                    self.too_long = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The event for a scanned code
+pub fn lookup(scan: &[u8]) -> InputEvent {
+    let product = core::str::from_utf8(scan)
+        .ok()
+        .and_then(|code| catalogue::by_barcode(code.trim()));
+    match product {
+        Some(product) => InputEvent::ProduceButtonPressed {
+            image: product.image,
+            price: product.price,
+        },
+        None => InputEvent::UnknownBarcode,
+    }
+}
+
+#[cfg(not(test))]
+pub async fn run(mut rx: BufferedUartRx) {
+    let mut buf = [0u8; 16];
+    let mut lines = LineBuffer::new();
+    loop {
+        let n = match rx.read(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("Scanner read failed: {:?}", e);
+                continue;
+            }
+        };
+        for &byte in &buf[..n] {
+            if let Some(scan) = lines.push(byte) {
+                info!("Scanned {=[u8]:a}", scan);
+                INPUT_EVENTS.send(lookup(&scan)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::Images;
+
+    /// Every scan in a stream of bytes from the scanner
+    fn scans(bytes: &[u8]) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let mut lines = LineBuffer::new();
+        bytes.iter().filter_map(|&b| lines.push(b)).map(|scan| scan.to_vec()).collect()
+    }
+
+    #[test]
+    fn cr_lf_and_crlf_all_end_a_line() {
+        let found = scans(b"2000000000015\r2000000000022\n2000000000039\r\n2000000000046\n");
+        assert_eq!(found, [&b"2000000000015"[..], b"2000000000022", b"2000000000039", b"2000000000046"]);
+    }
+
+    #[test]
+    fn blank_lines_are_dropped() {
+        assert!(scans(b"\r\n\n\r").is_empty());
+    }
+
+    #[test]
+    fn overlong_lines_are_dropped() {
+        let mut bytes = [b'1'; MAX_SCAN + 1].to_vec();
+        bytes.extend_from_slice(b"\r\n2000000000015\n");
+        assert_eq!(scans(&bytes), [b"2000000000015"]);
+
+        // Exactly MAX_SCAN still fits
+        let mut bytes = [b'1'; MAX_SCAN].to_vec();
+        bytes.push(b'\n');
+        assert_eq!(scans(&bytes), [[b'1'; MAX_SCAN]]);
+    }
+
+    #[test]
+    fn known_code_rings_up_its_product() {
+        for scan in [&b"2000000000015"[..], b" 2000000000015 "] {
+            assert!(matches!(
+                lookup(scan),
+                InputEvent::ProduceButtonPressed { image: Images::Banana, price: 2 }
+            ));
+        }
+    }
+
+    #[test]
+    fn upc_a_matches_its_ean_13() {
+        assert!(catalogue::barcode_matches("0036000291452", "036000291452"));
+        assert!(catalogue::barcode_matches("0036000291452", "0036000291452"));
+        // Only a leading zero can be left off
+        assert!(!catalogue::barcode_matches("2000000000015", "000000000015"));
+        assert!(!catalogue::barcode_matches("0036000291452", "36000291452"));
+    }
+
+    #[test]
+    fn unknown_code_is_an_error() {
+        for scan in [&b"1234"[..], b"000000000015", b"2000000000016", b"\xff\xfe"] {
+            assert!(matches!(lookup(scan), InputEvent::UnknownBarcode));
+        }
+    }
+}
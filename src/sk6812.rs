@@ -1,65 +1,84 @@
-use embassy_rp::clocks::clk_sys_freq;
-use embassy_rp::dma::{AnyChannel, Channel};
-use embassy_rp::pio::program::pio_asm;
-use embassy_rp::pio::{
-    Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection, StateMachine
-};
-use embassy_rp::dma::Channel as Peripheral;
-use embassy_rp::Peri;
-use embassy_time::Timer;
 use fixed::types::U24F8;
 
+use crate::color::Color;
+
+// Only the driver itself needs the hardware. Tests run on the host, so
+// they assemble with the pio crate that embassy-rp wraps.
+#[cfg(not(test))]
+use {
+    core::marker::PhantomData,
+    embassy_rp::clocks::clk_sys_freq,
+    embassy_rp::dma::{AnyChannel, Channel},
+    embassy_rp::dma::Channel as Peripheral,
+    embassy_rp::pio::program::{pio_asm, Program},
+    embassy_rp::pio::{
+        Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection, StateMachine
+    },
+    embassy_rp::Peri,
+    embassy_time::Timer,
+};
+#[cfg(test)]
+use pio::{pio_asm, Program};
 
-#[derive(Clone, Copy)]
-#[repr(C, packed)]
-pub struct RGBWParts {
-    pub w: u8,
-    pub b: u8,
-    pub r: u8,
-    pub g: u8,
-}
 
-#[derive(Clone, Copy)]
-pub union RGBW {
-    pub parts: RGBWParts,
-    pub raw: [u8; 4],
-    pub raw32: u32,
+/// The order a pixel takes its channels in, and how many it has. Each
+/// pixel goes to the PIO as one FIFO word, sent from the top bit down, so
+/// 24 bit pixels sit in the top three bytes.
+pub trait ColorOrder {
+    const BITS: u8;
+    fn encode(color: Color) -> u32;
 }
 
-impl RGBW {
-    pub const fn new(r: u8, g: u8, b: u8, w: u8) -> Self {
-        Self {
-            parts: RGBWParts { r, g, b, w },
-        }
+/// SK6812 RGBW
+pub struct Grbw;
+/// Some RGBW clones
+pub struct Rgbw;
+/// WS2812 and other RGB parts. There's no white channel, so white is mixed
+/// from the other three.
+pub struct Grb;
+
+impl ColorOrder for Grbw {
+    const BITS: u8 = 32;
+    fn encode(color: Color) -> u32 {
+        u32::from_be_bytes([color.g, color.r, color.b, color.w])
     }
+}
 
-    pub const fn black() -> Self {
-        Self::new(0, 0, 0, 0)
+impl ColorOrder for Rgbw {
+    const BITS: u8 = 32;
+    fn encode(color: Color) -> u32 {
+        u32::from_be_bytes([color.r, color.g, color.b, color.w])
     }
+}
 
-    pub const fn full_on() -> Self {
-        Self::new(255, 255, 255, 255)
+impl ColorOrder for Grb {
+    const BITS: u8 = 24;
+    fn encode(color: Color) -> u32 {
+        let mix = |c: u8| c.saturating_add(color.w);
+        u32::from_be_bytes([mix(color.g), mix(color.r), mix(color.b), 0])
     }
 }
 
+// A 1 is high for T1 + T2 cycles and a 0 for T1, out of ten at 8 MHz.
+// These need to match the defines in `program`.
 const T1: u8 = 2; // start bit
-const T2: u8 = 5; // data bit
-const T3: u8 = 3; // stop bit
+const T2: u8 = 3; // data bit
+const T3: u8 = 5; // stop bit
 const CYCLES_PER_BIT: u32 = (T1 + T2 + T3) as u32;
 
 /// This struct represents a ws2812 program loaded into pio instruction memory.
+#[cfg(not(test))]
 pub struct PioSk6812Program<'a, PIO: Instance> {
     prg: LoadedProgram<'a, PIO>,
 }
 
-impl<'a, PIO: Instance> PioSk6812Program<'a, PIO> {
-    /// Load the Sk6812 program into the given pio
-    pub fn new(common: &mut Common<'a, PIO>) -> Self {
-        let prg = pio_asm!(r#"
+/// The Sk6812 program, assembled but not loaded
+pub fn program() -> Program<32> {
+    pio_asm!(r#"
         .side_set 1
         .define T1 2
-        .define T2 5
-        .define T3 3
+        .define T2 3
+        .define T3 5
 
         .wrap_target
         bitloop:
@@ -70,20 +89,39 @@ impl<'a, PIO: Instance> PioSk6812Program<'a, PIO> {
         do_zero:
             nop            side 0 [T2 - 1]
         .wrap
-        "#);                
-        let prg = common.load_program(&prg.program);
+        "#).program
+}
+
+/// PIO clock divider to run the program at 800 kbit/s, for a `clk_sys` in
+/// kHz. Measured in kHz to avoid overflows.
+pub fn clock_divider(clk_sys_khz: u32) -> U24F8 {
+    let clock_freq = U24F8::from_num(clk_sys_khz);
+    let sk6812_freq = U24F8::from_num(800);
+    let bit_freq = sk6812_freq * CYCLES_PER_BIT;
+    clock_freq / bit_freq
+}
+
+#[cfg(not(test))]
+impl<'a, PIO: Instance> PioSk6812Program<'a, PIO> {
+    /// Load the Sk6812 program into the given pio
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        let prg = common.load_program(&program());
         Self { prg }
     }
 }
 
 /// Pio backed sk6812 driver
-/// Const N is the number of sk6812 leds attached to this pin
-pub struct PioSk6812<'d, PIO: Instance, const S: usize, const N: usize> {
+/// Const N is the number of sk6812 leds attached to this pin, and O the
+/// order they take their colours in
+#[cfg(not(test))]
+pub struct PioSk6812<'d, PIO: Instance, const S: usize, const N: usize, O: ColorOrder = Grbw> {
     dma: Peri<'d, AnyChannel>, // 
     sm: StateMachine<'d, PIO, S>,
+    order: PhantomData<O>,
 }
 
-impl<'d, P: Instance, const S: usize, const N: usize> PioSk6812<'d, P, S, N> {
+#[cfg(not(test))]
+impl<'d, P: Instance, const S: usize, const N: usize, O: ColorOrder> PioSk6812<'d, P, S, N, O> {
     /// Configure a pio state machine to use the loaded Sk6812 program.
     pub fn new(
         pio: &mut Common<'d, P>,
@@ -102,17 +140,14 @@ impl<'d, P: Instance, const S: usize, const N: usize> PioSk6812<'d, P, S, N> {
 
         cfg.use_program(&program.prg, &[&out_pin]);
 
-        // Clock config, measured in kHz to avoid overflows
-        let clock_freq = U24F8::from_num(clk_sys_freq() / 1000);
-        let sk6812_freq = U24F8::from_num(800);
-        let bit_freq = sk6812_freq * CYCLES_PER_BIT;
-        cfg.clock_divider = clock_freq / bit_freq;
+        // Clock config
+        cfg.clock_divider = clock_divider(clk_sys_freq() / 1000);
 
         // FIFO config
         cfg.fifo_join = FifoJoin::TxOnly;
         cfg.shift_out = ShiftConfig {
             auto_fill: true,
-            threshold: 32,
+            threshold: O::BITS,
             direction: ShiftDirection::Left,
         };
 
@@ -124,16 +159,14 @@ impl<'d, P: Instance, const S: usize, const N: usize> PioSk6812<'d, P, S, N> {
         Self {
             dma: dma.into(),
             sm,
+            order: PhantomData,
         }
     }
 
-    /// Write a buffer of RGBW to the ws2812 string
-    pub async fn write(&mut self, colors: &[RGBW; N]) {
+    /// Write a buffer of colours to the string
+    pub async fn write(&mut self, colors: &[Color; N]) {
         // Precompute the word bytes from the colors
-        let mut words = [0u32; N];
-        for i in 0..N {
-            words[i] = unsafe { colors[i].raw32 };
-        }
+        let words = colors.map(O::encode);
 
         // DMA transfer
         self.sm.tx().dma_push(self.dma.reborrow(), &words, false).await;
@@ -141,3 +174,202 @@ impl<'d, P: Instance, const S: usize, const N: usize> PioSk6812<'d, P, S, N> {
         Timer::after_micros(55).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::ops::RangeInclusive;
+
+    use super::*;
+    use crate::pio_sim;
+
+    /// SK6812 datasheet limits, in nanoseconds
+    const T0H_NS: RangeInclusive<u32> = 150..=450;
+    const T1H_NS: RangeInclusive<u32> = 450..=750;
+    const T0L_NS: RangeInclusive<u32> = 750..=1050;
+    const T1L_NS: RangeInclusive<u32> = 450..=750;
+    const BIT_NS: RangeInclusive<u32> = 650..=1850;
+
+    /// Sent twice, so the autopull between words is covered too
+    const PATTERN: u32 = 0xA5F0_0FC3;
+
+    /// A datasheet timing a program breaks, with what it actually gave in
+    /// nanoseconds
+    #[derive(Debug, PartialEq, Eq)]
+    enum TimingError {
+        T0H(u32),
+        T1H(u32),
+        T0L(u32),
+        T1L(u32),
+        Bit(u32),
+    }
+
+    /// Each bit of the test pattern that `program` sends at a `clk_sys` in
+    /// kHz, as (bit, high time, period) in nanoseconds
+    fn bits(program: &Program<32>, clk_sys_khz: u32) -> Vec<(bool, u32, u32)> {
+        // Picoseconds per PIO cycle, averaging out the fractional divider
+        let cycle_ps = clock_divider(clk_sys_khz).to_bits() as u64 * 1_000_000_000 / 256 / clk_sys_khz as u64;
+        let ns = |cycles: u32| (cycles as u64 * cycle_ps / 1000) as u32;
+
+        let waveform = pio_sim::run(program, &[PATTERN, PATTERN], 32).unwrap();
+        waveform
+            .pulses()
+            .enumerate()
+            .map(|(i, (high, period))| (PATTERN >> (31 - i % 32) & 1 != 0, ns(high), ns(period)))
+            .collect()
+    }
+
+    /// Check every bit of the test pattern against the datasheet
+    fn check_timing(program: &Program<32>, clk_sys_khz: u32) -> Result<(), TimingError> {
+        for (bit, high, period) in bits(program, clk_sys_khz) {
+            if bit && !T1H_NS.contains(&high) {
+                return Err(TimingError::T1H(high));
+            }
+            if !bit && !T0H_NS.contains(&high) {
+                return Err(TimingError::T0H(high));
+            }
+            let low = period - high;
+            if bit && !T1L_NS.contains(&low) {
+                return Err(TimingError::T1L(low));
+            }
+            if !bit && !T0L_NS.contains(&low) {
+                return Err(TimingError::T0L(low));
+            }
+            if !BIT_NS.contains(&period) {
+                return Err(TimingError::Bit(period));
+            }
+        }
+        Ok(())
+    }
+
+    /// The divider gives 8 MHz at each of these, so the times come out the
+    /// same: T0H 250 ns, T0L 1000 ns, T1H 625 ns, T1L 625 ns and a 1250 ns
+    /// bit
+    fn assert_timing(clk_sys_khz: u32) {
+        let bits = bits(&program(), clk_sys_khz);
+        // The last pulse is cut short by the stall, so it isn't counted
+        assert_eq!(bits.len(), 63);
+        for (bit, high, period) in bits {
+            assert_eq!(high, if bit { 625 } else { 250 });
+            assert_eq!(period - high, if bit { 625 } else { 1000 });
+            assert_eq!(period, 1250);
+        }
+        assert_eq!(check_timing(&program(), clk_sys_khz), Ok(()));
+    }
+
+    const COLOR: Color = Color::new(0x11, 0x22, 0x33, 0x44);
+
+    #[test]
+    fn grbw_words() {
+        assert_eq!(Grbw::BITS, 32);
+        assert_eq!(Grbw::encode(COLOR), 0x2211_3344);
+        // The word the old packed w, b, r, g union gave on the little endian
+        // RP2040, so the default order sends exactly what it used to
+        let raw32 = u32::from_le_bytes([COLOR.w, COLOR.b, COLOR.r, COLOR.g]);
+        let (r, g, b, w) = (COLOR.r as u32, COLOR.g as u32, COLOR.b as u32, COLOR.w as u32);
+        assert_eq!(raw32, g << 24 | r << 16 | b << 8 | w);
+        assert_eq!(Grbw::encode(COLOR), raw32);
+    }
+
+    #[test]
+    fn rgbw_words() {
+        assert_eq!(Rgbw::BITS, 32);
+        assert_eq!(Rgbw::encode(COLOR), 0x1122_3344);
+    }
+
+    #[test]
+    fn grb_words() {
+        assert_eq!(Grb::BITS, 24);
+        assert_eq!(Grb::encode(Color::rgb(0x11, 0x22, 0x33)), 0x2211_3300);
+        // White is mixed into each channel, saturating
+        assert_eq!(Grb::encode(COLOR), 0x6655_7700);
+        assert_eq!(Grb::encode(Color::new(200, 100, 250, 100)), 0xC8FF_FF00);
+    }
+
+    /// The bits the strip sees for some pixels, read back from the emulated
+    /// pin. A one is high for longer than the start bit.
+    fn sent<O: ColorOrder>(pixels: &[Color]) -> Vec<bool> {
+        let words: Vec<u32> = pixels.iter().map(|&c| O::encode(c)).collect();
+        let waveform = pio_sim::run(&program(), &words, O::BITS).unwrap();
+        waveform.pulses().map(|(high, _)| high > T1 as u32).collect()
+    }
+
+    /// The top `O::BITS` of each pixel's word, leaving off the last bit as
+    /// `Waveform::pulses` does
+    fn expected<O: ColorOrder>(pixels: &[Color]) -> Vec<bool> {
+        let mut bits: Vec<bool> = pixels
+            .iter()
+            .flat_map(|&c| (0..O::BITS).map(move |i| O::encode(c) >> (31 - i) & 1 != 0))
+            .collect();
+        bits.pop();
+        bits
+    }
+
+    #[test]
+    fn each_order_sends_its_bits_top_first() {
+        let pixels = [COLOR, Color::new(0xF0, 0x0F, 0xA5, 0x5A)];
+        assert_eq!(sent::<Grbw>(&pixels), expected::<Grbw>(&pixels));
+        assert_eq!(sent::<Rgbw>(&pixels), expected::<Rgbw>(&pixels));
+        // Only 24 bits a pixel, with the autopull moving on to the next word
+        assert_eq!(sent::<Grb>(&pixels).len(), 47);
+        assert_eq!(sent::<Grb>(&pixels), expected::<Grb>(&pixels));
+    }
+
+    #[test]
+    fn timing_at_125_mhz() {
+        assert_timing(125_000);
+    }
+
+    #[test]
+    fn timing_at_133_mhz() {
+        assert_timing(133_000);
+    }
+
+    #[test]
+    fn timing_at_48_mhz() {
+        assert_timing(48_000);
+    }
+
+    #[test]
+    fn old_timing_fails_t1h() {
+        let old = pio_asm!(r#"
+            .side_set 1
+            .define T1 2
+            .define T2 5
+            .define T3 3
+
+            .wrap_target
+            bitloop:
+                out x, 1       side 0 [T3 - 1]
+                jmp !x do_zero side 1 [T1 - 1]
+                jmp bitloop    side 1 [T2 - 1]
+
+            do_zero:
+                nop            side 0 [T2 - 1]
+            .wrap
+            "#).program;
+        assert_eq!(check_timing(&old, 125_000), Err(TimingError::T1H(875)));
+    }
+
+    /// Both high times are fine, but the lows are too short. The bit is
+    /// still well inside its limits, so only the low checks catch it.
+    #[test]
+    fn short_low_fails_t1l() {
+        let short = pio_asm!(r#"
+            .side_set 1
+            .define T1 2
+            .define T2 3
+            .define T3 2
+
+            .wrap_target
+            bitloop:
+                out x, 1       side 0 [T3 - 1]
+                jmp !x do_zero side 1 [T1 - 1]
+                jmp bitloop    side 1 [T2 - 1]
+
+            do_zero:
+                nop            side 0 [T2 - 1]
+            .wrap
+            "#).program;
+        assert_eq!(check_timing(&short, 125_000), Err(TimingError::T1L(250)));
+    }
+}
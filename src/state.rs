@@ -1,86 +1,416 @@
+use core::fmt::Write as _;
+
+use defmt::{info, warn};
 use embassy_executor::task;
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select, Either};
+use embassy_rp::clocks::RoscRng;
+use embassy_time::{Duration, Instant, Timer};
 use escpos_embedded::Image;
+use heapless::{String, Vec};
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::{basket::{group_lines, InputEvent, LineItem, MAX_LINES}, catalogue, clock::{self, TimeField, Timestamp}, config::{self, IdleAction, PrintMode, Recovery}, counters::CounterStore, journal::{Journal, Recovered}, color::Color, led::{self, Fault, Keyframe, Layer, Pattern, Pixels}, images::Images, printer::{DriverEvent, PRINT_EVENTS}, promotions::{self, Discount}, storage::Storage, tax};
+
+/// Longest text put in the QR code on a receipt
+const SUMMARY_LEN: usize = 256;
+pub type Summary = String<SUMMARY_LEN>;
+
+/// Plain text copy of a receipt, for the QR code under the total
+fn receipt_summary(receipt: u32, lines: &[LineItem], discounts: &[Discount], total: u16) -> Option<Summary> {
+    if !config::RECEIPT_QR {
+        return None;
+    }
+    // Anything that doesn't fit is left off
+    let mut text = Summary::new();
+    let _ = writeln!(text, "Receipt #{}", receipt);
+    for group in group_lines(lines) {
+        let _ = writeln!(text, "{:?} x{} £{}", group.image, group.quantity, group.price());
+    }
+    for discount in discounts {
+        let _ = writeln!(text, "{:?} deal -£{}", discount.image, discount.amount);
+    }
+    let _ = write!(text, "Total £{}", total);
+    Some(text)
+}
+
+/// Two presses of total this close together while idle reprint the last receipt
+const REPRINT_WINDOW: Duration = Duration::from_millis(1000);
 
-use crate::{led::{Led, LedState, LED_STATE, RGBW}, printer::{DriverEvent, Images, PRINT_EVENTS}};
+/// Steady LED while a basket is open
+const IN_TRANSACTION: Pattern = Pattern::Solid(Color::new(0, 0, 0, 55));
+/// LED breathing before an idle timeout
+const IDLE_WARNING: Pattern = Pattern::Pulse {
+    color: Color::rgb(137, 99, 0),
+    period: Duration::from_millis(1000),
+};
+/// How long the flash in a product's colour lasts when it's rung up
+const PRODUCT_FLASH: Duration = Duration::from_millis(500);
+/// Two quick green blinks as the basket is totalled
+const TOTAL_FEEDBACK: Pattern = Pattern::Blink {
+    color: Color::rgb(0, 188, 0),
+    times: 2,
+    on: Duration::from_millis(120),
+    off: Duration::from_millis(120),
+};
+/// A slower amber fade as something is voided
+const VOID_FEEDBACK: Pattern = Pattern::Flash {
+    color: Color::rgb(188, 70, 0),
+    duration: Duration::from_millis(800),
+};
+/// Colours chasing round for a coupon winner
+const CELEBRATION: Pattern = Pattern::Sequence {
+    frames: &[
+        Keyframe { color: Color::rgb(188, 0, 0), duration: Duration::from_millis(300) },
+        Keyframe { color: Color::rgb(0, 188, 0), duration: Duration::from_millis(300) },
+        Keyframe { color: Color::rgb(0, 0, 188), duration: Duration::from_millis(300) },
+        Keyframe { color: Color::rgb(188, 0, 0), duration: Duration::from_millis(300) },
+        Keyframe { color: Color::rgb(0, 188, 0), duration: Duration::from_millis(300) },
+        Keyframe { color: Color::rgb(0, 0, 188), duration: Duration::from_millis(300) },
+    ],
+    repeat: false,
+};
+const ERROR_BLINK: Pattern = Pattern::Blink {
+    color: Color::rgb(188, 0, 0),
+    times: 3,
+    on: Duration::from_millis(200),
+    off: Duration::from_millis(200),
+};
 
-pub enum InputEvent {
-    ProduceButtonPressed{ image: Images, price: u16 },
-    VoidButtonPressed,
-    TotalButtonPressed,
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Transaction {
+    Idle,
+    Open,
+    /// A subtotal has been printed, and the next press of total will finish
+    Subtotalled,
 }
 
-// Queue
+// Queue, with room for a burst of key presses so the button tasks don't
+// stall while we're busy
 pub static INPUT_EVENTS: embassy_sync::channel::Channel<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
     InputEvent,
-    1,
+    8,
 > = embassy_sync::channel::Channel::new();
 
-async fn err_toggle() {
-    for _ in 0..3 {
-        LED_STATE.send(LedState::Color(RGBW::new(128, 0, 0, 0))).await;
-        Timer::after(Duration::from_millis(200)).await;
-        LED_STATE.send(LedState::Default).await;
-        Timer::after(Duration::from_millis(200)).await;
+/// What the base LED layer is showing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Base {
+    Idle,
+    Transaction,
+    Warning,
+}
+
+fn set_base(base: Base) {
+    match base {
+        Base::Idle => led::clear(Layer::Base, Pixels::STATUS),
+        Base::Transaction => led::set(Layer::Base, Pixels::STATUS, IN_TRANSACTION, None),
+        Base::Warning => led::set(Layer::Base, Pixels::STATUS, IDLE_WARNING, None),
     }
 }
 
-async fn set_led_state(color: Option<RGBW>) {
-    let state = match color {
-        Some(c) => LedState::Color(c),
-        None => LedState::Default,
-    };
-    LED_STATE.send(state).await;
-    LED_STATE.send(LedState::Noop).await;
+/// Flash a pattern over the base layer, which the LED task goes back to
+/// once it finishes
+fn show(pattern: Pattern) {
+    led::set(Layer::Feedback, Pixels::STATUS, pattern, None);
+}
+
+/// Flash the status LED and the one under the product's key in its colour
+fn flash_product(image: Images) {
+    if let Some(id) = catalogue::product_id(image) {
+        let color = catalogue::PRODUCTS[id as usize].color;
+        let pattern = Pattern::Flash { color, duration: PRODUCT_FLASH };
+        led::set(Layer::Feedback, Pixels::STATUS.union(Pixels::key(id)), pattern, None);
+    }
+}
+
+fn err_toggle() {
+    show(ERROR_BLINK);
+}
+
+/// Keys that step the selected field while setting the time
+const TIME_UP_KEY: u8 = 0;
+const TIME_DOWN_KEY: u8 = 1;
+
+/// Handle a key press while setting the time from the keypad.
+///
+/// The first two produce keys step the current field up and down, total
+/// moves on to the next field (and sets the clock after the last one) and
+/// void gives up. Only the time that's set is printed, to save paper.
+/// Returns the new editor state, or None once finished.
+async fn edit_time(field: TimeField, mut time: Timestamp, event: InputEvent) -> Option<(TimeField, Timestamp)> {
+    match event {
+        InputEvent::ProduceButtonPressed { image, .. } => {
+            match catalogue::product_id(image) {
+                Some(TIME_UP_KEY) => time.adjust(field, true),
+                Some(TIME_DOWN_KEY) => time.adjust(field, false),
+                _ => {
+                    err_toggle();
+                    return Some((field, time));
+                }
+            }
+            flash_product(image);
+            Some((field, time))
+        }
+        InputEvent::TotalButtonPressed | InputEvent::TotalButtonLongPressed => match field.next() {
+            Some(next) => Some((next, time)),
+            None => {
+                match clock::set(&time) {
+                    Ok(()) => {
+                        info!("Clock set to {:?}", time);
+                        PRINT_EVENTS.send(DriverEvent::PrintTime { time }).await;
+                    }
+                    Err(e) => {
+                        warn!("Failed to set clock: {:?}", e);
+                        err_toggle();
+                    }
+                }
+                None
+            }
+        },
+        InputEvent::VoidButtonPressed | InputEvent::VoidButtonLongPressed => {
+            info!("Cancelled setting the time");
+            None
+        }
+        InputEvent::UnknownBarcode => {
+            err_toggle();
+            Some((field, time))
+        }
+    }
 }
 
 #[task]
-pub async fn main_state() {
+pub async fn main_state(mut storage: Storage) {
 
-    let mut in_transaction: bool = false;
+    let mut transaction = Transaction::Idle;
     let mut current_price: u16 = 0;
+    let mut lines: Vec<LineItem, MAX_LINES> = Vec::new();
+    let mut last_receipt: Vec<LineItem, MAX_LINES> = Vec::new();
+    let mut last_receipt_number: u32 = 0;
+    let mut last_receipt_time: Option<Timestamp> = None;
+    let mut last_idle_total: Option<Instant> = None;
+    let mut time_setting: Option<(TimeField, Timestamp)> = None;
+    let mut rng = SmallRng::seed_from_u64(RoscRng.next_u64());
+
+    let (mut counter_store, mut counters) = CounterStore::load(&mut storage);
+    info!("Last receipt was #{}", counters.receipt);
+
+    let (mut journal, recovered) = Journal::open(&mut storage);
+    match recovered {
+        Recovered::Clean => {}
+        Recovered::Unfinished { receipt: recovered_receipt, lines: recovered_lines } => match config::RECOVERY {
+            Recovery::Resume => {
+                info!("Resuming interrupted transaction #{} with {} lines", recovered_receipt, recovered_lines.len());
+                transaction = Transaction::Open;
+                current_price = recovered_lines.iter().map(|line| line.price).sum();
+                lines = recovered_lines;
+            }
+            Recovery::Void => {
+                info!("Voiding interrupted transaction");
+                PRINT_EVENTS.send(DriverEvent::PrintInterrupted).await;
+                journal.finish(&mut storage);
+            }
+        },
+        Recovered::Corrupt => {
+            warn!("Journal was corrupt, assuming a transaction was interrupted");
+            led::raise(Fault::JournalCorrupt);
+            PRINT_EVENTS.send(DriverEvent::PrintInterrupted).await;
+        }
+    }
+
+    let mut last_activity = Instant::now();
+    let mut warning_shown = false;
+    let mut base = Base::Idle;
 
     loop {
-        match INPUT_EVENTS.receive().await {
-            InputEvent::ProduceButtonPressed { image, price } => {
-                set_led_state(Some(RGBW::new(0,0, 64, 0))).await;
-                if ! in_transaction {
-                    current_price = 0;
-                    in_transaction = true;
-                    PRINT_EVENTS.send(DriverEvent::PrintHeader).await;
-                }
+        let wanted = match transaction {
+            Transaction::Idle => Base::Idle,
+            _ if warning_shown => Base::Warning,
+            _ => Base::Transaction,
+        };
+        if wanted != base {
+            base = wanted;
+            set_base(base);
+        }
 
-                if current_price + price as u16 > 999 {
-                    err_toggle().await;
-                } else {
-                    current_price += price as u16;
-                    PRINT_EVENTS.send(DriverEvent::PrintLine { image, price }).await;
+        let event = if transaction == Transaction::Idle {
+            INPUT_EVENTS.receive().await
+        } else {
+            let timeout_at = last_activity + config::IDLE_TIMEOUT;
+            let warning_at = timeout_at - config::IDLE_WARNING;
+            let now = Instant::now();
+            if now >= timeout_at {
+                // Finish the abandoned basket as if the button had been pressed
+                info!("Transaction abandoned");
+                match config::IDLE_ACTION {
+                    IdleAction::Void => InputEvent::VoidButtonPressed,
+                    IdleAction::Total => InputEvent::TotalButtonLongPressed,
                 }
-            }
-            InputEvent::VoidButtonPressed => {
-                set_led_state(Some(RGBW::new(0,0, 64, 0))).await;
-                if in_transaction {
-                    PRINT_EVENTS.send(DriverEvent::PrintVoid).await;
-                    in_transaction = false;
-                    current_price = 0;
+            } else {
+                let wake_at = if now >= warning_at {
+                    if !warning_shown {
+                        warning_shown = true;
+                        continue;
+                    }
+                    timeout_at
                 } else {
-                    err_toggle().await;
+                    warning_at
+                };
+                match select(INPUT_EVENTS.receive(), Timer::at(wake_at)).await {
+                    Either::First(event) => event,
+                    Either::Second(()) => continue,
                 }
             }
-            InputEvent::TotalButtonPressed => {
-                set_led_state(Some(RGBW::new(0,0, 64, 0))).await;
-                if in_transaction {
-                    PRINT_EVENTS.send(DriverEvent::PrintTotal { price: current_price}).await;
-                    in_transaction = false;
-                    current_price = 0;
-                } else {
-                    err_toggle().await;
+        };
+        last_activity = Instant::now();
+        warning_shown = false;
+        // A key press acknowledges a corrupt journal found at boot
+        led::clear_fault(Fault::JournalCorrupt);
+
+        if let Some((field, time)) = time_setting {
+            time_setting = edit_time(field, time, event).await;
+        } else {
+            match event {
+                InputEvent::ProduceButtonPressed { image, price } => {
+                    flash_product(image);
+                    if transaction == Transaction::Idle {
+                        current_price = 0;
+                        lines.clear();
+                        // The number it gets if it's totalled. Nothing is used
+                        // up until then, so a void doesn't leave a gap.
+                        journal.begin(&mut storage, counters.receipt + 1);
+                        if let PrintMode::Streaming = config::PRINT_MODE {
+                            PRINT_EVENTS.send(DriverEvent::PrintHeader { time: clock::now() }).await;
+                        }
+                    }
+
+                    transaction = Transaction::Open;
+
+                    let item = LineItem { image, price };
+                    if current_price + price as u16 > 999 || lines.push(item).is_err() {
+                        err_toggle();
+                    } else {
+                        current_price += price as u16;
+                        journal.line(&mut storage, &item);
+                        if let PrintMode::Streaming = config::PRINT_MODE {
+                            PRINT_EVENTS.send(DriverEvent::PrintLine { image, price }).await;
+                        }
+                    }
+                }
+                event @ (InputEvent::VoidButtonPressed | InputEvent::VoidButtonLongPressed) => {
+                    show(VOID_FEEDBACK);
+                    let long_press = matches!(event, InputEvent::VoidButtonLongPressed);
+                    if transaction != Transaction::Idle {
+                        PRINT_EVENTS.send(DriverEvent::PrintVoid).await;
+                        journal.finish(&mut storage);
+                        transaction = Transaction::Idle;
+                        current_price = 0;
+                    } else if long_press {
+                        // Z report: print the day's figures and start a new day
+                        PRINT_EVENTS.send(DriverEvent::PrintZReport {
+                            receipts: counters.daily_receipts,
+                            takings: counters.daily_takings,
+                            last_receipt: counters.receipt,
+                        }).await;
+                        counters.daily_receipts = 0;
+                        counters.daily_takings = 0;
+                        counter_store.save(&mut storage, &counters);
+                    } else {
+                        err_toggle();
+                    }
+                }
+                event @ (InputEvent::TotalButtonPressed | InputEvent::TotalButtonLongPressed) => {
+                    show(TOTAL_FEEDBACK);
+                    let long_press = matches!(event, InputEvent::TotalButtonLongPressed);
+                    match transaction {
+                        Transaction::Open if !long_press => {
+                            PRINT_EVENTS.send(DriverEvent::PrintSubtotal { price: current_price }).await;
+                            transaction = Transaction::Subtotalled;
+                        }
+                        Transaction::Open | Transaction::Subtotalled => {
+                            counters.receipt += 1;
+                            counters.daily_receipts += 1;
+                            let receipt = counters.receipt;
+                            let discounts = promotions::discounts(&lines);
+                            let tax = tax::breakdown(&lines, &discounts);
+                            let total = current_price - discounts.iter().map(|d| d.amount).sum::<u16>();
+                            let summary = receipt_summary(receipt, &lines, &discounts, total);
+                            let time = clock::now();
+                            match config::PRINT_MODE {
+                                PrintMode::Streaming => {
+                                    for discount in discounts {
+                                        PRINT_EVENTS.send(DriverEvent::PrintDiscount { discount }).await;
+                                    }
+                                    PRINT_EVENTS.send(DriverEvent::PrintTotal { receipt, price: total, tax, summary, time }).await;
+                                }
+                                PrintMode::Buffered => {
+                                    PRINT_EVENTS.send(DriverEvent::PrintReceipt {
+                                        receipt,
+                                        time,
+                                        groups: group_lines(&lines),
+                                        discounts,
+                                        tax,
+                                        summary,
+                                        copy: false,
+                                    }).await;
+                                }
+                            }
+                            journal.finish(&mut storage);
+                            counters.daily_takings += total as u32;
+                            counters.customers += 1;
+                            counter_store.save(&mut storage, &counters);
+
+                            let stamps = counters.customers % config::LOYALTY_EVERY.max(1);
+                            if config::STAMP_CARD {
+                                let stamps = if stamps == 0 { config::LOYALTY_EVERY } else { stamps };
+                                PRINT_EVENTS.send(DriverEvent::PrintStampCard { stamps }).await;
+                            }
+                            if stamps == 0 {
+                                info!("Customer {} wins a coupon", counters.customers);
+                                let code = rng.gen_range(0..1_000_000);
+                                PRINT_EVENTS.send(DriverEvent::PrintCoupon { code }).await;
+                                show(CELEBRATION);
+                            }
+                            transaction = Transaction::Idle;
+                            current_price = 0;
+                            last_receipt = lines.clone();
+                            last_receipt_number = receipt;
+                            last_receipt_time = time;
+                        }
+                        Transaction::Idle if long_press => {
+                            let time = clock::now().unwrap_or(Timestamp::DEFAULT);
+                            info!("Setting the time, starting from {:?}", time);
+                            time_setting = Some((TimeField::Year, time));
+                        }
+                        Transaction::Idle => {
+                            if last_receipt.is_empty() {
+                                err_toggle();
+                            } else if last_idle_total.is_some_and(|at| at.elapsed() < REPRINT_WINDOW) {
+                                last_idle_total = None;
+                                let discounts = promotions::discounts(&last_receipt);
+                                let subtotal: u16 = last_receipt.iter().map(|line| line.price).sum();
+                                let total = subtotal - discounts.iter().map(|d| d.amount).sum::<u16>();
+                                PRINT_EVENTS.send(DriverEvent::PrintReceipt {
+                                    receipt: last_receipt_number,
+                                    time: last_receipt_time,
+                                    groups: group_lines(&last_receipt),
+                                    tax: tax::breakdown(&last_receipt, &discounts),
+                                    summary: receipt_summary(last_receipt_number, &last_receipt, &discounts, total),
+                                    discounts,
+                                    copy: true,
+                                }).await;
+                            } else {
+                                last_idle_total = Some(Instant::now());
+                            }
+                        }
+                    }
+                }
+                InputEvent::UnknownBarcode => {
+                    warn!("Scanned a barcode that isn't in the catalogue");
+                    err_toggle();
                 }
             }
         }
-        Timer::after(Duration::from_millis(400)).await;
-        set_led_state(None).await;
     }
 
 }
\ No newline at end of file
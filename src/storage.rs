@@ -0,0 +1,71 @@
+use embassy_rp::flash::{Blocking, Error, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_rp::Peri;
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// The top 64K of flash is kept out of the program image by `memory.x`
+const STORAGE_OFFSET: u32 = (FLASH_SIZE - 64 * 1024) as u32;
+
+/// A range of whole erase sectors within the storage area
+pub struct Region {
+    offset: u32,
+    sectors: u32,
+}
+
+impl Region {
+    pub const fn size(&self) -> u32 {
+        self.sectors * ERASE_SIZE as u32
+    }
+}
+
+pub const JOURNAL: Region = Region {
+    offset: STORAGE_OFFSET,
+    sectors: 2,
+};
+
+pub const COUNTERS: Region = Region {
+    offset: JOURNAL.offset + JOURNAL.size(),
+    sectors: 2,
+};
+
+/// Owns the flash, and keeps reads and writes inside their regions
+pub struct Storage {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+}
+
+impl Storage {
+    pub fn new(flash: Peri<'static, FLASH>) -> Self {
+        Self {
+            flash: Flash::new_blocking(flash),
+        }
+    }
+
+    pub fn read(&mut self, region: &Region, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        if offset + buf.len() as u32 > region.size() {
+            return Err(Error::OutOfBounds);
+        }
+        self.flash.blocking_read(region.offset + offset, buf)
+    }
+
+    /// Program bytes that were previously erased
+    pub fn write(&mut self, region: &Region, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        if offset + buf.len() as u32 > region.size() {
+            return Err(Error::OutOfBounds);
+        }
+        self.flash.blocking_write(region.offset + offset, buf)
+    }
+
+    pub fn erase(&mut self, region: &Region) -> Result<(), Error> {
+        self.flash
+            .blocking_erase(region.offset, region.offset + region.size())
+    }
+
+    pub fn erase_sector(&mut self, region: &Region, sector: u32) -> Result<(), Error> {
+        if sector >= region.sectors {
+            return Err(Error::OutOfBounds);
+        }
+        let start = region.offset + sector * ERASE_SIZE as u32;
+        self.flash.blocking_erase(start, start + ERASE_SIZE as u32)
+    }
+}
@@ -0,0 +1,218 @@
+//! VAT on the items in a basket.
+//!
+//! Catalogue prices include VAT, so the VAT is worked out backwards from the
+//! gross amount. Amounts here are in pence so that rounding only happens
+//! once, on the total for each rate, as it would on a real VAT receipt.
+
+use defmt::Format;
+
+use crate::basket::LineItem;
+use crate::catalogue;
+use crate::config;
+use crate::promotions::Discount;
+
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum TaxClass {
+    Standard,
+    Reduced,
+    Zero,
+}
+
+/// Amounts in pence
+#[derive(Clone, Copy, Default, Format)]
+pub struct TaxBreakdown {
+    pub net: u32,
+    pub vat: u32,
+    pub gross: u32,
+}
+
+impl TaxBreakdown {
+    /// Net and VAT in whole pounds, as the rest of the receipt is printed.
+    /// The VAT is rounded to the nearest pound with halves rounded up, and
+    /// the net is what's left, so the two still add up to the total.
+    pub fn in_pounds(&self) -> (u32, u32) {
+        let vat = (self.vat + 50) / 100;
+        (self.gross / 100 - vat, vat)
+    }
+}
+
+/// The VAT included in a gross amount at `rate` basis points, rounded to the
+/// nearest penny with halves rounded up
+pub fn vat_included(gross: u32, rate: u32) -> u32 {
+    let numerator = gross as u64 * rate as u64;
+    let denominator = 10_000 + rate as u64;
+    ((2 * numerator + denominator) / (2 * denominator)) as u32
+}
+
+/// Split a basket into net and VAT. Each discount is shared out over the
+/// products its offer was for by price, and each share comes off the
+/// amount taxed at that product's rate.
+pub fn breakdown(lines: &[LineItem], discounts: &[Discount]) -> TaxBreakdown {
+    let class_index = |image| {
+        let class = catalogue::tax_class(image);
+        config::TAX_RATES.iter().position(|(c, _)| *c == class)
+    };
+    let unit_price = |image| {
+        catalogue::product_id(image)
+            .and_then(catalogue::product)
+            .map_or(0, |p| p.price as u32)
+    };
+
+    let mut gross_by_class = [0u32; config::TAX_RATES.len()];
+    let mut result = TaxBreakdown::default();
+    for line in lines {
+        let gross = line.price as u32 * 100;
+        result.gross += gross;
+        if let Some(i) = class_index(line.image) {
+            gross_by_class[i] += gross;
+        }
+    }
+    for discount in discounts {
+        let amount = discount.amount as u32 * 100;
+        result.gross = result.gross.saturating_sub(amount);
+        let full_price: u32 = discount.products.iter().map(|&p| unit_price(p)).sum();
+        let mut left = amount;
+        for (n, &product) in discount.products.iter().enumerate() {
+            // Whatever rounding leaves goes on the last one
+            let share = if n + 1 == discount.products.len() {
+                left
+            } else {
+                amount * unit_price(product) / full_price.max(1)
+            };
+            left -= share;
+            if let Some(i) = class_index(product) {
+                gross_by_class[i] = gross_by_class[i].saturating_sub(share);
+            }
+        }
+    }
+    result.vat = config::TAX_RATES
+        .iter()
+        .zip(gross_by_class)
+        .map(|((_, rate), gross)| vat_included(gross, *rate))
+        .sum();
+    result.net = result.gross - result.vat;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::Images;
+    use crate::promotions;
+
+    fn line(image: Images) -> LineItem {
+        let product = catalogue::PRODUCTS.iter().find(|p| p.image == image).unwrap();
+        LineItem { image, price: product.price }
+    }
+
+    fn basket(images: &[Images]) -> Vec<LineItem> {
+        images.iter().map(|&image| line(image)).collect()
+    }
+
+    fn split(tax: TaxBreakdown) -> (u32, u32, u32) {
+        (tax.net, tax.vat, tax.gross)
+    }
+
+    #[test]
+    fn half_pennies_round_up_at_20_percent() {
+        // VAT is a sixth of the gross, so 3p carries exactly half a penny
+        assert_eq!(vat_included(3, 2000), 1);
+        assert_eq!(vat_included(9, 2000), 2);
+        assert_eq!(vat_included(15, 2000), 3);
+        // Less than a half rounds down
+        assert_eq!(vat_included(2, 2000), 0);
+        assert_eq!(vat_included(8, 2000), 1);
+        assert_eq!(vat_included(120, 2000), 20);
+    }
+
+    #[test]
+    fn rounds_to_nearest_at_5_percent() {
+        // VAT is a twenty-first of the gross, which never lands on a half,
+        // so check either side of one
+        assert_eq!(vat_included(10, 500), 0);
+        assert_eq!(vat_included(11, 500), 1);
+        assert_eq!(vat_included(31, 500), 1);
+        assert_eq!(vat_included(32, 500), 2);
+        assert_eq!(vat_included(105, 500), 5);
+    }
+
+    #[test]
+    fn zero_rate_has_no_vat() {
+        assert_eq!(vat_included(0, 0), 0);
+        assert_eq!(vat_included(12_345, 0), 0);
+    }
+
+    #[test]
+    fn zero_rated_basket() {
+        let lines = basket(&[Images::Banana, Images::Bread, Images::Eggs]);
+        assert_eq!(split(breakdown(&lines, &[])), (1000, 0, 1000));
+    }
+
+    #[test]
+    fn mixed_rate_basket() {
+        // Juice and pie are standard rated, £9 with £1.50 VAT
+        let lines = basket(&[Images::Juice, Images::Banana, Images::Pie, Images::Cheese]);
+        assert_eq!(split(breakdown(&lines, &[])), (1350, 150, 1500));
+    }
+
+    #[test]
+    fn rounds_once_per_rate() {
+        // Two juices rounded separately would be 17p + 17p
+        let lines = basket(&[Images::Juice, Images::Juice]);
+        assert_eq!(split(breakdown(&lines, &[])), (167, 33, 200));
+    }
+
+    #[test]
+    fn discount_comes_off_its_own_rate() {
+        let lines = basket(&[Images::Pie, Images::Pie, Images::Banana]);
+        let discounts = [Discount { image: Images::Pie, amount: 8, products: &[Images::Pie] }];
+        // £8 of pie left, of which 133p is VAT; the banana has none
+        assert_eq!(split(breakdown(&lines, &discounts)), (867, 133, 1000));
+    }
+
+    #[test]
+    fn meal_deal_discount_is_shared_by_price() {
+        // Bread and cheese are zero rated, juice is standard. The £2 off
+        // the deal is a tenth of its £10 full price, so 20p comes off the
+        // juice, leaving 80p with 13p VAT.
+        let lines = basket(&[Images::Bread, Images::Cheese, Images::Juice]);
+        let discounts = promotions::discounts(&lines);
+        assert_eq!(split(breakdown(&lines, &discounts)), (787, 13, 800));
+    }
+
+    #[test]
+    fn net_and_vat_add_up_to_gross() {
+        // None, one or two of each product in every combination, with
+        // whatever offers they get
+        for mask in 0..3u32.pow(catalogue::PRODUCTS.len() as u32) {
+            let mut lines = Vec::new();
+            let mut counts = mask;
+            for product in &catalogue::PRODUCTS {
+                for _ in 0..counts % 3 {
+                    lines.push(line(product.image));
+                }
+                counts /= 3;
+            }
+            let discounts = promotions::discounts(&lines);
+            let tax = breakdown(&lines, &discounts);
+            let gross: u32 = lines.iter().map(|l| l.price as u32 * 100).sum::<u32>()
+                - discounts.iter().map(|d| d.amount as u32 * 100).sum::<u32>();
+            assert_eq!(tax.gross, gross);
+            assert_eq!(tax.net + tax.vat, tax.gross);
+            let (net, vat) = tax.in_pounds();
+            assert_eq!((net + vat) * 100, tax.gross);
+        }
+    }
+
+    #[test]
+    fn whole_pounds_round_the_vat() {
+        let lines = basket(&[Images::Juice, Images::Banana, Images::Pie, Images::Cheese]);
+        // £1.50 VAT is half way, so rounds up
+        assert_eq!(breakdown(&lines, &[]).in_pounds(), (13, 2));
+        let lines = basket(&[Images::Juice, Images::Juice]);
+        assert_eq!(breakdown(&lines, &[]).in_pounds(), (2, 0));
+        let lines = basket(&[Images::Pie, Images::Pie, Images::Pie]);
+        // £24 with £4 VAT, exactly
+        assert_eq!(breakdown(&lines, &[]).in_pounds(), (20, 4));
+    }
+}
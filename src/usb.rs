@@ -0,0 +1,140 @@
+//! USB serial console, for setting the clock and printing shelf labels
+//! from a computer.
+//!
+//! Commands are one per line:
+//!   `time`                        print the current time
+//!   `time YYYY-MM-DD HH:MM[:SS]`  set the clock
+//!   `label N`                     print the shelf label for key N, from 1
+
+use core::fmt::Write;
+
+use defmt::{info, warn};
+use embassy_futures::join::join;
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::Driver;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::{Builder, Config};
+use heapless::{String, Vec};
+use static_cell::StaticCell;
+
+use crate::catalogue;
+use crate::clock::{self, Timestamp};
+use crate::printer::{DriverEvent, PRINT_EVENTS};
+
+const MAX_PACKET: u16 = 64;
+const MAX_LINE: usize = 64;
+
+type Serial = CdcAcmClass<'static, Driver<'static, USB>>;
+
+pub async fn run(driver: Driver<'static, USB>) {
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+    static STATE: StaticCell<State> = StaticCell::new();
+
+    let mut config = Config::new(0x1209, 0x0001);
+    config.manufacturer = Some("tilltoy");
+    config.product = Some("Toy till");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        &mut [],
+        CONTROL_BUF.init([0; 64]),
+    );
+    let mut class = CdcAcmClass::new(&mut builder, STATE.init(State::new()), MAX_PACKET);
+    let mut usb = builder.build();
+
+    let console_loop = async {
+        loop {
+            class.wait_connection().await;
+            info!("USB serial connected");
+            let _ = console(&mut class).await;
+            info!("USB serial disconnected");
+        }
+    };
+    join(usb.run(), console_loop).await;
+}
+
+async fn console(class: &mut Serial) -> Result<(), EndpointError> {
+    let mut packet = [0u8; MAX_PACKET as usize];
+    let mut line: Vec<u8, MAX_LINE> = Vec::new();
+    loop {
+        let n = class.read_packet(&mut packet).await?;
+        for &byte in &packet[..n] {
+            match byte {
+                b'\r' | b'\n' => {
+                    if !line.is_empty() {
+                        let reply = command(core::str::from_utf8(&line).unwrap_or(""));
+                        class.write_packet(reply.as_bytes()).await?;
+                        line.clear();
+                    }
+                }
+                _ => {
+                    if line.push(byte).is_err() {
+                        // Too long to be a valid command, drop it
+                        line.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn command(line: &str) -> String<MAX_LINE> {
+    let mut reply = String::new();
+    let (name, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+    match (name, args.trim()) {
+        ("time", "") => match clock::now() {
+            Some(t) => {
+                let _ = write!(
+                    reply,
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}\r\n",
+                    t.year, t.month, t.day, t.hour, t.minute, t.second
+                );
+            }
+            None => {
+                let _ = reply.push_str("no time set\r\n");
+            }
+        },
+        ("time", args) => match Timestamp::parse(args) {
+            Some(time) => match clock::set(&time) {
+                Ok(()) => {
+                    info!("Clock set to {:?} over USB", time);
+                    let _ = reply.push_str("ok\r\n");
+                }
+                Err(e) => {
+                    warn!("Failed to set clock: {:?}", e);
+                    let _ = reply.push_str("error: clock not running\r\n");
+                }
+            },
+            None => {
+                let _ = reply.push_str("error: expected YYYY-MM-DD HH:MM[:SS]\r\n");
+            }
+        },
+        ("label", args) => match args.parse::<u8>().ok().and_then(|key| key.checked_sub(1)) {
+            Some(product) if catalogue::product(product).is_some() => {
+                match PRINT_EVENTS.try_send(DriverEvent::PrintShelfLabel { product }) {
+                    Ok(()) => {
+                        let _ = reply.push_str("ok\r\n");
+                    }
+                    Err(_) => {
+                        let _ = reply.push_str("error: printer busy\r\n");
+                    }
+                }
+            }
+            _ => {
+                let _ = write!(reply, "error: expected a key from 1 to {}\r\n", catalogue::PRODUCTS.len());
+            }
+        },
+        _ => {
+            let _ = reply.push_str("error: unknown command\r\n");
+        }
+    }
+    reply
+}